@@ -1,8 +1,31 @@
+mod progress;
+
 use anyhow::Result;
 use clap::{Parser, Subcommand, ValueEnum, Args};
-use deduper_engine::{filtering::Filter, hashing, quarantine, scan_directory, FileEntry};
+use deduper_engine::{
+    cancel::{CancelToken, StopReason},
+    chunking, db, duplicate_groups,
+    excludes::Excludes,
+    filtering::Filter,
+    hardlinks, hashing,
+    hidden::HiddenPolicy,
+    io, list_files, manifest, order,
+    outcome::{ScanError, ScanErrorKind},
+    pipeline, quarantine, report, resume,
+    roots::{self, ScanRoot},
+    scan_with, stream,
+    symlinks::SymlinkPolicy,
+    verify, FileEntry, ScanOptions,
+};
+use progress::ProgressObserver;
 use regex::Regex;
-use std::{collections::HashMap, fs, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 #[derive(Parser)]
 #[command(author, version, about = "Intelligent File Deduplicator")]
@@ -10,6 +33,28 @@ use std::{collections::HashMap, fs, path::Path};
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Lower CPU and I/O priority to run as a polite background job (Linux).
+    #[arg(long, global = true, value_enum, default_value_t = PriorityMode::Normal)]
+    priority: PriorityMode,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum PriorityMode {
+    Normal,
+    /// nice 10, lowest best-effort I/O priority.
+    Background,
+    /// nice 19, idle I/O class: only read when the disk is otherwise unused.
+    Idle,
+}
+
+impl From<PriorityMode> for io::Priority {
+    fn from(p: PriorityMode) -> Self {
+        match p {
+            PriorityMode::Normal => io::Priority::Normal,
+            PriorityMode::Background => io::Priority::Background,
+            PriorityMode::Idle => io::Priority::Idle,
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -22,16 +67,35 @@ enum Commands {
     Quarantine(QuarantineArgs),
     /// Restore a single file from quarantine.
     Recover(RecoverArgs),
+    /// Measure block-level overlap between files.
+    Chunks(ChunksArgs),
+    /// Write or verify sha256sum/b3sum-style checksum manifests.
+    #[command(subcommand)]
+    Manifest(ManifestCommand),
+}
+
+#[derive(Subcommand)]
+enum ManifestCommand {
+    /// Hash a tree and print `<digest>  <path>` lines.
+    Export(ManifestExportArgs),
+    /// Verify files against a manifest, like `sha256sum -c`.
+    Check(ManifestCheckArgs),
 }
 
 #[derive(Args)]
 struct FindArgs {
     path: Option<String>,
+    /// Exit with an error if any path could not be walked or read.
+    #[arg(long)]
+    strict: bool,
+    #[command(flatten)]
+    walk: WalkArgs,
 }
 
 #[derive(Args)]
 struct ScanArgs {
-    path: Option<String>,
+    #[command(flatten)]
+    roots: RootArgs,
     #[arg(long, default_value_t = 0)]
     min_size: u64,
     #[arg(long, default_value = "txt")]
@@ -40,19 +104,59 @@ struct ScanArgs {
     pattern: String,
     #[arg(long, value_enum, default_value_t = HashAlgo::Sha256)]
     algo: HashAlgo,
+    /// Extra digests to compute in the same pass, e.g. `--also sha256,blake3`.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    also: Vec<HashAlgo>,
     #[arg(long)]
     output: Option<String>,
+    #[arg(long, value_enum, default_value_t = ReportFormat::Json)]
+    format: ReportFormat,
+    /// Exit with an error if any path could not be walked or read.
+    #[arg(long)]
+    strict: bool,
+    /// Reuse `--algo` digests from this manifest instead of reading the files.
+    #[arg(long)]
+    manifest: Option<String>,
+    /// Also hash each file inside .zip, .tar, .tar.gz and .tar.zst archives,
+    /// reported as `ARCHIVE!/MEMBER`. Members are filtered like files.
+    #[arg(long)]
+    archives: bool,
+    #[command(flatten)]
+    io: IoArgs,
+    #[command(flatten)]
+    walk: WalkArgs,
 }
 
 #[derive(Args)]
 struct QuarantineArgs {
-    path: Option<String>,
+    #[command(flatten)]
+    roots: RootArgs,
     #[arg(long, default_value_t = 0)]
     min_size: u64,
     #[arg(long, default_value = "txt")]
     ext: String,
     #[arg(long, default_value = ".*")]
     pattern: String,
+    #[arg(long, value_enum, default_value_t = HashAlgo::Sha256)]
+    algo: HashAlgo,
+    /// Compare candidates byte for byte before moving anything.
+    #[arg(long)]
+    paranoid: bool,
+    /// Also quarantine files that contain only zero bytes. These are listed
+    /// separately and left alone by default.
+    #[arg(long)]
+    include_zero: bool,
+    /// Exit with an error if any path could not be walked or read.
+    #[arg(long)]
+    strict: bool,
+    /// Reuse `--algo` digests from this manifest instead of reading the files.
+    /// Needs `--paranoid`, since files may have changed since it was written.
+    #[arg(long, requires = "paranoid")]
+    manifest: Option<String>,
+    #[command(flatten)]
+    io: IoArgs,
+    #[command(flatten)]
+    walk: WalkArgs,
 }
 
 #[derive(Args)]
@@ -60,6 +164,300 @@ struct RecoverArgs {
     file: String,
 }
 
+#[derive(Args)]
+struct ChunksArgs {
+    path: Option<String>,
+    #[arg(long, default_value_t = 0)]
+    min_size: u64,
+    #[arg(long, default_value = ".*")]
+    pattern: String,
+    #[arg(long, value_enum, default_value_t = HashAlgo::Blake3)]
+    algo: HashAlgo,
+    /// Average chunk size in KiB (min is a quarter, max four times this).
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u32).range(1..=4096))]
+    avg_chunk_kib: u32,
+    /// Number of most-overlapping file pairs to print.
+    #[arg(long, default_value_t = 20)]
+    top: usize,
+    #[arg(long)]
+    output: Option<String>,
+    /// Exit with an error if any path could not be walked or read.
+    #[arg(long)]
+    strict: bool,
+    #[command(flatten)]
+    walk: WalkArgs,
+}
+
+#[derive(Args)]
+struct ManifestExportArgs {
+    path: Option<String>,
+    #[arg(long, default_value_t = 0)]
+    min_size: u64,
+    #[arg(long, default_value = ".*")]
+    pattern: String,
+    #[arg(long, value_enum, default_value_t = HashAlgo::Sha256)]
+    algo: HashAlgo,
+    /// Write here instead of stdout.
+    #[arg(long)]
+    output: Option<String>,
+    /// Exit with an error if any path could not be walked or read.
+    #[arg(long)]
+    strict: bool,
+    #[command(flatten)]
+    io: IoArgs,
+    #[command(flatten)]
+    walk: WalkArgs,
+}
+
+#[derive(Args)]
+struct ManifestCheckArgs {
+    manifest: String,
+    #[arg(long, value_enum, default_value_t = HashAlgo::Sha256)]
+    algo: HashAlgo,
+    /// Directory the manifest paths are relative to; defaults to the manifest's own.
+    #[arg(long)]
+    base: Option<String>,
+}
+
+/// Read tuning shared by every command that hashes whole files.
+#[derive(Args)]
+struct IoArgs {
+    /// Bytes per read call.
+    #[arg(long, default_value_t = 8192)]
+    read_size: usize,
+    /// How hashing reads should treat the page cache.
+    #[arg(long, value_enum, default_value_t = CacheMode::Normal)]
+    cache: CacheMode,
+    /// Concurrent readers for the device holding PATH, e.g. `/mnt/hdd=1`.
+    /// Repeatable; other devices get 1 if rotational, else one per CPU.
+    #[arg(long, value_name = "PATH=N", value_parser = parse_device_threads)]
+    device_threads: Vec<(PathBuf, usize)>,
+    /// Order files are read in; `disk` follows physical layout, for HDDs.
+    #[arg(long, value_enum, default_value_t = OrderMode::Walk)]
+    order: OrderMode,
+    /// Memory-map files of at least SIZE (e.g. `4GiB`) and hash each one on
+    /// several threads. Only blake3 splits one file across cores; sha256 and
+    /// xxh3 still run on one thread each, beside the other digests. Ignored
+    /// with `--cache` other than normal or with `--max-read-rate`.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    parallel_above: Option<u64>,
+    /// Cap on the combined read rate of all workers, e.g. `50MB/s` or `1GiB`.
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    max_read_rate: Option<u64>,
+    /// Checkpoint progress on very large files in `<root>/.deduper/index`,
+    /// so an interrupted run continues where it stopped. Only sha256 and
+    /// blake3 can be checkpointed; with xxh3 among the digests asked for,
+    /// files are hashed from the start every time, with a warning.
+    #[arg(long)]
+    resume: bool,
+}
+
+impl IoArgs {
+    /// The checkpoint index for `root`, if `--resume` was given.
+    fn checkpoints(&self, root: &str) -> Result<Option<db::Index>> {
+        if !self.resume {
+            return Ok(None);
+        }
+        Ok(Some(db::Index::open(Path::new(root))?))
+    }
+}
+
+/// The trees a command scans.
+#[derive(Args)]
+struct RootArgs {
+    /// Directories to scan (default `.`); their files may be quarantined.
+    paths: Vec<String>,
+    /// A directory whose files are compared against and kept, never
+    /// quarantined. Repeatable.
+    #[arg(long, value_name = "PATH")]
+    reference: Vec<String>,
+}
+
+impl RootArgs {
+    /// The candidate roots, then the reference roots.
+    fn roots(&self) -> Vec<ScanRoot> {
+        let mut roots: Vec<_> = self.paths.iter().map(ScanRoot::candidate).collect();
+        if roots.is_empty() {
+            roots.push(ScanRoot::candidate("."));
+        }
+        roots.extend(self.reference.iter().map(ScanRoot::reference));
+        roots
+    }
+
+    /// The first candidate root, where `--resume` keeps its index.
+    fn primary(&self) -> &str {
+        self.paths.first().map_or(".", String::as_str)
+    }
+}
+
+/// How the directory walk treats what it finds.
+#[derive(Args)]
+struct WalkArgs {
+    /// What to do with symbolic links: leave them out, scan what they point
+    /// to, or list each link itself. Files reached through a link are never
+    /// quarantined.
+    #[arg(long, value_enum, default_value_t = SymlinkMode::Skip)]
+    symlinks: SymlinkMode,
+    /// Leave out paths matching this gitignore-style pattern, relative to
+    /// each root; excluded directories are not descended into. Repeatable.
+    #[arg(long = "exclude", value_name = "PATTERN")]
+    excludes: Vec<String>,
+    /// Do not read .gitignore, .ignore and .dedupignore files.
+    #[arg(long)]
+    no_ignore: bool,
+    /// Descend at most this many directories below each root; 1 means only
+    /// the files directly in it.
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+    /// Leave out files fewer than this many levels below their root.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    min_depth: usize,
+    /// Stay on the device of each root, skipping mount points below it.
+    #[arg(long)]
+    one_file_system: bool,
+    /// What to do with dotfiles, dot-directories and files marked hidden
+    /// (e.g. by a `user.hidden` xattr): scan them, leave them out, or scan
+    /// nothing else.
+    #[arg(long, value_enum, default_value_t = HiddenMode::Include)]
+    hidden: HiddenMode,
+}
+
+impl WalkArgs {
+    /// Default scan options, walking as asked.
+    fn options(&self) -> Result<ScanOptions> {
+        Ok(ScanOptions {
+            symlinks: self.symlinks.into(),
+            excludes: Excludes::new(&self.excludes, !self.no_ignore)?,
+            max_depth: self.max_depth,
+            min_depth: self.min_depth,
+            one_file_system: self.one_file_system,
+            hidden: self.hidden.into(),
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum HiddenMode {
+    Include,
+    Exclude,
+    Only,
+}
+
+impl From<HiddenMode> for HiddenPolicy {
+    fn from(m: HiddenMode) -> Self {
+        match m {
+            HiddenMode::Include => HiddenPolicy::Include,
+            HiddenMode::Exclude => HiddenPolicy::Exclude,
+            HiddenMode::Only => HiddenPolicy::Only,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum SymlinkMode {
+    Skip,
+    Follow,
+    Report,
+}
+
+impl From<SymlinkMode> for SymlinkPolicy {
+    fn from(m: SymlinkMode) -> Self {
+        match m {
+            SymlinkMode::Skip => SymlinkPolicy::Skip,
+            SymlinkMode::Follow => SymlinkPolicy::Follow,
+            SymlinkMode::Report => SymlinkPolicy::Report,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum OrderMode {
+    Walk,
+    Disk,
+}
+
+impl From<OrderMode> for order::ReadOrder {
+    fn from(o: OrderMode) -> Self {
+        match o {
+            OrderMode::Walk => order::ReadOrder::Walk,
+            OrderMode::Disk => order::ReadOrder::Disk,
+        }
+    }
+}
+
+fn parse_device_threads(s: &str) -> Result<(PathBuf, usize), String> {
+    let (path, n) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected PATH=N, got {:?}", s))?;
+    let n = n.parse().map_err(|e| format!("bad thread count {:?}: {}", n, e))?;
+    Ok((PathBuf::from(path), n))
+}
+
+/// Bytes per second from `1000`, `50MB/s`, `1.5GiB` and the like.
+fn parse_rate(s: &str) -> Result<u64, String> {
+    parse_size(s.trim().trim_end_matches("/s"))
+}
+
+/// Bytes from `1000`, `50MB`, `1.5GiB` and the like.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let t = s.trim();
+    let split = t
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(t.len());
+    let (num, unit) = t.split_at(split);
+    let num: f64 = num.parse().map_err(|_| format!("bad rate {:?}", s))?;
+    let mult = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kb" => 1e3,
+        "m" | "mb" => 1e6,
+        "g" | "gb" => 1e9,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        u => return Err(format!("unknown unit {:?} in {:?}", u, s)),
+    };
+    match (num * mult) as u64 {
+        0 => Err(format!("must be positive, got {:?}", s)),
+        n => Ok(n),
+    }
+}
+
+impl From<&IoArgs> for io::ReadOptions {
+    fn from(a: &IoArgs) -> Self {
+        io::ReadOptions {
+            buf_size: a.read_size,
+            cache: match a.cache {
+                CacheMode::Normal => io::CachePolicy::Normal,
+                CacheMode::DropBehind => io::CachePolicy::DropBehind,
+                CacheMode::Direct => io::CachePolicy::Direct,
+            },
+            throttle: a
+                .max_read_rate
+                .map(|r| std::sync::Arc::new(io::Throttle::new(r))),
+            parallel_above: a.parallel_above,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum CacheMode {
+    Normal,
+    /// posix_fadvise(DONTNEED) pages right after hashing them (Linux).
+    DropBehind,
+    /// O_DIRECT reads that bypass the page cache (Linux).
+    Direct,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum ReportFormat {
+    Json,
+    /// One row per file: path, then one column per digest.
+    Csv,
+    /// A page listing the duplicate groups, written once the scan is done.
+    Html,
+}
+
 #[derive(ValueEnum, Clone, Copy)]
 enum HashAlgo {
     Sha256,
@@ -79,23 +477,31 @@ impl From<HashAlgo> for hashing::Algo {
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    io::set_priority(cli.priority.into())?;
 
     match cli.command {
         // ---------------- find ----------------
         Commands::Find(args) => {
             let root = args.path.unwrap_or_else(|| ".".to_string());
-            for e in walkdir::WalkDir::new(&root)
-                .into_iter()
-                .filter_map(Result::ok)
-                .filter(|e| e.file_type().is_file())
-            {
-                println!("{}", e.path().display());
+            let filter = Filter {
+                min_size: 0,
+                max_size: None,
+                ext: None,
+                pattern: Regex::new(".*")?,
+                since: None,
+            };
+            let opts = args.walk.options()?;
+            let (files, errors) = list_files(&[ScanRoot::candidate(&root)], &filter, &opts);
+            for path in files {
+                println!("{}", path.display());
             }
+            report_errors(&errors, args.strict)?;
         }
 
         // ---------------- scan ----------------
         Commands::Scan(args) => {
-            let root = args.path.unwrap_or_else(|| ".".to_string());
+            let roots = args.roots.roots();
+            let progress = Arc::new(ProgressObserver::new());
             let filter = Filter {
                 min_size: args.min_size,
                 max_size: None,
@@ -103,18 +509,60 @@ fn main() -> Result<()> {
                 pattern: Regex::new(&args.pattern)?,
                 since: None,
             };
-            let entries = scan_directory(Path::new(&root), &filter, args.algo.into())?;
-            println!("Hashed {} files", entries.len());
+            let opts = ScanOptions {
+                algo: args.algo.into(),
+                extra_algos: args.also.into_iter().map(Into::into).collect(),
+                precomputed: load_precomputed(args.manifest.as_deref(), args.algo.into(), &roots)?,
+                io: (&args.io).into(),
+                device_threads: args.io.device_threads.clone(),
+                order: args.io.order.into(),
+                checkpoints: args.io.checkpoints(args.roots.primary())?,
+                observer: progress.clone(),
+                cancel: cancel_on_interrupt()?,
+                deadline: None,
+                archives: args.archives,
+                ..args.walk.options()?
+            };
+            warn_unresumable(&opts);
+            let mut report = match &args.output {
+                Some(out) => Some(ReportWriter::create(out, args.format, &opts)?),
+                None => None,
+            };
+            let (mut hashed, mut zero) = (0, 0);
+            let mut errors = Vec::new();
+            let mut results = stream::scan_roots(&roots, &filter, &opts);
+            for r in &mut results {
+                let e = match r {
+                    Ok(e) => e,
+                    Err(err) => {
+                        errors.push(err);
+                        continue;
+                    }
+                };
+                hashed += 1;
+                zero += e.all_zero as usize;
+                if let Some(r) = &mut report {
+                    r.write(&e)?;
+                }
+            }
+            progress.bar.finish_and_clear();
+            if zero > 0 {
+                println!("Hashed {} files ({} all-zero)", hashed, zero);
+            } else {
+                println!("Hashed {} files", hashed);
+            }
 
-            if let Some(out) = args.output {
-                fs::write(&out, serde_json::to_string_pretty(&entries)?)?;
+            if let (Some(r), Some(out)) = (report, args.output) {
+                r.finish()?;
                 println!("Report written to {}", out);
             }
+            report_errors(&errors, args.strict)?;
+            check_complete(results.stopped())?;
         }
 
         // --------------- quarantine -----------
         Commands::Quarantine(args) => {
-            let root = args.path.unwrap_or_else(|| ".".to_string());
+            let roots = args.roots.roots();
             let filter = Filter {
                 min_size: args.min_size,
                 max_size: None,
@@ -122,8 +570,65 @@ fn main() -> Result<()> {
                 pattern: Regex::new(&args.pattern)?,
                 since: None,
             };
-            let entries = scan_directory(Path::new(&root), &filter, hashing::Algo::Sha256)?;
-            move_duplicates(&entries)?;
+            let opts = ScanOptions {
+                algo: args.algo.into(),
+                precomputed: load_precomputed(args.manifest.as_deref(), args.algo.into(), &roots)?,
+                io: (&args.io).into(),
+                device_threads: args.io.device_threads.clone(),
+                order: args.io.order.into(),
+                checkpoints: args.io.checkpoints(args.roots.primary())?,
+                cancel: cancel_on_interrupt()?,
+                ..args.walk.options()?
+            };
+            warn_unresumable(&opts);
+            let scan = pipeline::find_duplicates_in(&roots, &filter, &opts)?;
+            let s = scan.stats;
+            println!(
+                "Scanned {} files: {} unique by size, {} by partial hash, {} by full hash, \
+                 {} duplicates",
+                s.scanned, s.dropped_at_size, s.dropped_at_partial, s.dropped_at_full, s.confirmed
+            );
+            report_errors(&scan.errors, args.strict)?;
+            if let Some(reason) = scan.stopped {
+                anyhow::bail!("scan stopped ({}); nothing was quarantined", reason);
+            }
+            for set in hardlinks::hardlink_sets(&scan.entries) {
+                let paths: Vec<_> = set.iter().map(|e| e.path.as_str()).collect();
+                println!("Hardlinked, one file on disk: {}", paths.join(", "));
+            }
+            let mut groups = duplicate_groups(&scan.entries);
+            if args.paranoid {
+                let checked = verify::verify_groups_with(&groups, &opts.io)?;
+                for c in &checked.collisions {
+                    eprintln!("Hash collision on {}:", c.hash);
+                    for v in &c.variants {
+                        eprintln!("  distinct content: {}", v.join(", "));
+                    }
+                }
+                report_errors(&checked.errors, args.strict)?;
+                groups = checked.groups;
+            }
+            // Another path to the same file would be all that is left.
+            for g in &mut groups {
+                let (linked, direct): (Vec<_>, Vec<_>) =
+                    g.drain(..).partition(|e| e.via_symlink || e.symlink.is_some());
+                for e in linked {
+                    println!("Reached through a symlink, not quarantined: {}", e.path);
+                }
+                *g = direct;
+            }
+            groups.retain(|g| hardlinks::physical_copies(g).len() > 1);
+            if !args.include_zero {
+                let (zero, rest): (Vec<_>, Vec<_>) =
+                    groups.into_iter().partition(|g| g.iter().any(|e| e.all_zero));
+                for g in &zero {
+                    let paths: Vec<_> = g.iter().map(|e| e.path.as_str()).collect();
+                    println!("All-zero files, not quarantined: {}", paths.join(", "));
+                }
+                groups = rest;
+            }
+            println!("Reclaimable: {} bytes", hardlinks::reclaimable_bytes(&groups));
+            move_duplicates(&groups)?;
         }
 
         // ---------------- recover -------------
@@ -131,20 +636,302 @@ fn main() -> Result<()> {
             let dest = quarantine::recover(&args.file)?;
             println!("Recovered to {}", dest.display());
         }
+
+        // ---------------- chunks --------------
+        Commands::Chunks(args) => {
+            let root = args.path.unwrap_or_else(|| ".".to_string());
+            let filter = Filter {
+                min_size: args.min_size,
+                max_size: None,
+                ext: None,
+                pattern: Regex::new(&args.pattern)?,
+                since: None,
+            };
+            let avg = args.avg_chunk_kib * 1024;
+            let params = hashing::ChunkParams {
+                min: avg / 4,
+                avg,
+                max: avg * 4,
+            };
+            let chunked = chunking::chunk_directory_with(
+                Path::new(&root),
+                &filter,
+                args.algo.into(),
+                params,
+                &args.walk.options()?,
+            )?;
+            let files = chunked.files;
+            let report = chunking::analyze(&files);
+            println!(
+                "Chunked {} files: {} bytes total, {} unique, dedup ratio {:.2}",
+                files.len(),
+                report.total_bytes,
+                report.unique_bytes,
+                report.dedup_ratio
+            );
+            for p in report.pairs.iter().take(args.top) {
+                println!("{:>12} bytes shared: {} <-> {}", p.shared, p.a, p.b);
+            }
+
+            if let Some(out) = args.output {
+                fs::write(&out, serde_json::to_string_pretty(&report)?)?;
+                println!("Report written to {}", out);
+            }
+            report_errors(&chunked.errors, args.strict)?;
+        }
+
+        // ---------------- manifest ------------
+        Commands::Manifest(ManifestCommand::Export(args)) => {
+            let root = args.path.unwrap_or_else(|| ".".to_string());
+            let progress = Arc::new(ProgressObserver::new());
+            let filter = Filter {
+                min_size: args.min_size,
+                max_size: None,
+                ext: None,
+                pattern: Regex::new(&args.pattern)?,
+                since: None,
+            };
+            let opts = ScanOptions {
+                algo: args.algo.into(),
+                io: (&args.io).into(),
+                device_threads: args.io.device_threads.clone(),
+                order: args.io.order.into(),
+                checkpoints: args.io.checkpoints(&root)?,
+                observer: progress.clone(),
+                cancel: cancel_on_interrupt()?,
+                ..args.walk.options()?
+            };
+            warn_unresumable(&opts);
+            let mut scan = scan_with(Path::new(&root), &filter, &opts)?;
+            progress.bar.finish_and_clear();
+            report_errors(&scan.errors, args.strict)?;
+            let entries = &mut scan.entries;
+            entries.sort_by(|a, b| a.path.cmp(&b.path));
+            let text = manifest::render(entries, Path::new(&root), opts.algo)?;
+            match args.output {
+                Some(out) => fs::write(out, text)?,
+                None => print!("{}", text),
+            }
+            check_complete(scan.stopped)?;
+        }
+
+        Commands::Manifest(ManifestCommand::Check(args)) => {
+            let text = fs::read_to_string(&args.manifest)?;
+            let entries = manifest::parse(&text, args.algo.into())?;
+            let base = match args.base {
+                Some(b) => b.into(),
+                None => Path::new(&args.manifest)
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default(),
+            };
+            let results = manifest::check(&entries, &base);
+            let (mut failed, mut missing) = (0, 0);
+            for r in &results {
+                let label = match r.status {
+                    manifest::Status::Ok => "OK",
+                    manifest::Status::Failed => {
+                        failed += 1;
+                        "FAILED"
+                    }
+                    manifest::Status::Missing => {
+                        missing += 1;
+                        "MISSING"
+                    }
+                };
+                println!("{}: {}", r.path, label);
+            }
+            if failed + missing > 0 {
+                anyhow::bail!(
+                    "{} of {} computed checksums did NOT match, {} listed files are missing",
+                    failed,
+                    results.len() - missing,
+                    missing
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `--resume` cannot checkpoint xxh3, so with it among the digests every
+/// file is hashed from the start. Say so rather than quietly not resuming.
+fn warn_unresumable(opts: &ScanOptions) {
+    if opts.checkpoints.is_none() {
+        return;
+    }
+    let mut algos = std::iter::once(&opts.algo).chain(&opts.extra_algos);
+    if let Some(a) = algos.find(|&&a| !resume::is_resumable(a)) {
+        eprintln!(
+            "warning: --resume cannot checkpoint {}; files will be hashed from the start",
+            a.name()
+        );
+    }
+}
+
+/// Print every path the scan could not handle, then a count per kind, to
+/// stderr. With `strict`, any such path fails the command.
+fn report_errors(errors: &[ScanError], strict: bool) -> Result<()> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let mut by_kind: BTreeMap<ScanErrorKind, usize> = BTreeMap::new();
+    for e in errors {
+        eprintln!("error: {}", e);
+        *by_kind.entry(e.kind).or_default() += 1;
+    }
+    let summary: Vec<_> = by_kind
+        .iter()
+        .map(|(kind, n)| format!("{} {}", n, kind))
+        .collect();
+    eprintln!("{} paths skipped: {}", errors.len(), summary.join(", "));
+    if strict {
+        anyhow::bail!("{} paths could not be scanned", errors.len());
     }
     Ok(())
 }
 
-/// Move every duplicate (same hash) to quarantine dir.
-fn move_duplicates(entries: &[FileEntry]) -> Result<()> {
-    let mut seen: HashMap<&str, &str> = HashMap::new();
-    for e in entries {
-        if let Some(orig) = seen.get(e.hash.as_str()) {
-            let dest = quarantine::quarantine(Path::new(&e.path))?;
-            println!("Duplicate of {} quarantined as {}", orig, dest.display());
-        } else {
-            seen.insert(e.hash.as_str(), e.path.as_str());
+/// A token tripped by Ctrl-C, so the scan can wind down and its partial
+/// results still be written. A second Ctrl-C exits at once.
+fn cancel_on_interrupt() -> Result<CancelToken> {
+    let token = CancelToken::new();
+    let handler = token.clone();
+    ctrlc::set_handler(move || {
+        if handler.is_cancelled() {
+            std::process::exit(130);
+        }
+        eprintln!("Interrupted; finishing up (Ctrl-C again to quit now)");
+        handler.cancel();
+    })?;
+    Ok(token)
+}
+
+/// Fail the command, once its partial output is written, if the scan
+/// stopped early.
+fn check_complete(stopped: Option<StopReason>) -> Result<()> {
+    match stopped {
+        Some(reason) => anyhow::bail!("scan stopped ({}); results are incomplete", reason),
+        None => Ok(()),
+    }
+}
+
+/// Keep one copy in every group, preferring those under `--reference`, and
+/// move every path of the removable copies to the quarantine dir.
+/// Hardlinks of a kept copy stay put.
+fn move_duplicates(groups: &[Vec<&FileEntry>]) -> Result<()> {
+    for g in groups {
+        let orig = &roots::keeper(g).path;
+        for copy in roots::removable_copies(g) {
+            for e in copy {
+                let dest = quarantine::quarantine(Path::new(&e.path))?;
+                println!("Duplicate of {} quarantined as {}", orig, dest.display());
+            }
         }
     }
     Ok(())
 }
+
+/// Read a manifest into `ScanOptions::precomputed`, or nothing if none was given.
+fn load_precomputed(
+    path: Option<&str>,
+    algo: hashing::Algo,
+    roots: &[ScanRoot],
+) -> Result<HashMap<PathBuf, hashing::Digest>> {
+    let Some(path) = path else {
+        return Ok(HashMap::new());
+    };
+    // Manifest paths are relative, so they could match a file under each root.
+    if roots.len() > 1 {
+        anyhow::bail!("--manifest can only be used with a single root");
+    }
+    let entries = manifest::parse(&fs::read_to_string(path)?, algo)?;
+    Ok(manifest::to_precomputed(&entries))
+}
+
+/// Writes a scan report entry by entry, so it never has to be held in memory,
+/// except for HTML which needs the whole scan to group it.
+struct ReportWriter {
+    path: PathBuf,
+    out: std::io::BufWriter<fs::File>,
+    format: ReportFormat,
+    empty: bool,
+    /// Whether CSV rows carry the symlink columns.
+    links: bool,
+    /// Entries kept for the HTML page.
+    entries: Vec<FileEntry>,
+}
+
+impl ReportWriter {
+    fn create(path: &str, format: ReportFormat, opts: &ScanOptions) -> Result<Self> {
+        let mut out = std::io::BufWriter::new(fs::File::create(path)?);
+        match format {
+            ReportFormat::Json => write!(out, "[")?,
+            // One digest column per algorithm.
+            ReportFormat::Csv => {
+                write!(out, "path")?;
+                for algo in std::iter::once(&opts.algo).chain(&opts.extra_algos) {
+                    write!(out, ",{}", algo.name())?;
+                }
+                if opts.symlinks != SymlinkPolicy::Skip {
+                    write!(out, ",via_symlink,symlink")?;
+                }
+                writeln!(out)?;
+            }
+            ReportFormat::Html => {}
+        }
+        Ok(Self {
+            path: path.into(),
+            out,
+            format,
+            empty: true,
+            links: opts.symlinks != SymlinkPolicy::Skip,
+            entries: Vec::new(),
+        })
+    }
+
+    fn write(&mut self, e: &FileEntry) -> Result<()> {
+        match self.format {
+            ReportFormat::Json => {
+                let sep = if self.empty { "" } else { "," };
+                let body = serde_json::to_string_pretty(e)?.replace('\n', "\n  ");
+                write!(self.out, "{}\n  {}", sep, body)?;
+            }
+            ReportFormat::Csv => {
+                write!(self.out, "{}", csv_field(&e.path))?;
+                for d in std::iter::once(&e.hash).chain(&e.extra) {
+                    write!(self.out, ",{}", d.to_hex())?;
+                }
+                if self.links {
+                    let target = e.symlink.as_deref().unwrap_or_default();
+                    write!(self.out, ",{},{}", e.via_symlink, csv_field(target))?;
+                }
+                writeln!(self.out)?;
+            }
+            ReportFormat::Html => self.entries.push(e.clone()),
+        }
+        self.empty = false;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        match self.format {
+            ReportFormat::Json => {
+                writeln!(self.out, "{}]", if self.empty { "" } else { "\n" })?;
+            }
+            ReportFormat::Csv => {}
+            ReportFormat::Html => {
+                report::html(&duplicate_groups(&self.entries), &self.path)?;
+            }
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
@@ -1,4 +1,12 @@
-use indicatif::{ProgressBar, ProgressStyle};
+use deduper_engine::{observer::ScanObserver, outcome::ScanError, FileEntry};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::{
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// How many hashed bytes between updates of the bar's message.
+const BYTES_STEP: u64 = 4 << 20;
 
 pub fn bar_for_len(len: u64, msg: &str) -> ProgressBar {
     let pb = ProgressBar::new(len);
@@ -7,6 +15,50 @@ pub fn bar_for_len(len: u64, msg: &str) -> ProgressBar {
             .unwrap()
             .progress_chars("=> "),
     );
-    pb.set_message(msg);
+    pb.set_message(msg.to_string());
     pb
 }
+
+/// Drives a files-done bar from scan events, with bytes hashed as its message.
+///
+/// The length grows as the walk finds files, so the bar fills up only once
+/// the walk is over. Files ruled out early or that fail count as done.
+/// Nothing is drawn when stderr is not a terminal.
+pub struct ProgressObserver {
+    pub bar: ProgressBar,
+    bytes: AtomicU64,
+}
+
+impl ProgressObserver {
+    pub fn new() -> Self {
+        Self {
+            bar: bar_for_len(0, "hashed"),
+            bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+impl ScanObserver for ProgressObserver {
+    fn file_matched(&self, _path: &Path, _size: u64) {
+        self.bar.inc_length(1);
+    }
+
+    fn bytes_hashed(&self, n: u64) {
+        let before = self.bytes.fetch_add(n, Ordering::Relaxed);
+        if before / BYTES_STEP != (before + n) / BYTES_STEP {
+            self.bar.set_message(format!("{} hashed", HumanBytes(before + n)));
+        }
+    }
+
+    fn file_hashed(&self, _entry: &FileEntry) {
+        self.bar.inc(1);
+    }
+
+    fn file_dropped(&self, _path: &Path) {
+        self.bar.inc(1);
+    }
+
+    fn error(&self, _err: &ScanError) {
+        self.bar.inc(1);
+    }
+}
@@ -1,9 +1,11 @@
 use std::process::Command;
 use tempfile::TempDir;
 use std::fs;
+#[allow(unused_imports)] // Kept from the first version of these tests.
 use std::io::Write;
 
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn test_find_command() {
     let temp_dir = TempDir::new().unwrap();
     
@@ -27,6 +29,7 @@ fn test_find_command() {
 }
 
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn test_scan_command() {
     let temp_dir = TempDir::new().unwrap();
     
@@ -59,6 +62,7 @@ fn test_scan_command() {
 }
 
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args, clippy::unnecessary_map_or)]
 fn test_quarantine_command() {
     let temp_dir = TempDir::new().unwrap();
     
@@ -87,3 +91,482 @@ fn test_quarantine_command() {
     
     assert_eq!(remaining_files.len(), 1);
 }
+
+#[test]
+fn test_quarantine_paranoid_command() {
+    let temp_dir = TempDir::new().unwrap();
+
+    fs::write(temp_dir.path().join("a.txt"), "paranoid duplicate").unwrap();
+    fs::write(temp_dir.path().join("b.txt"), "paranoid duplicate").unwrap();
+    fs::write(temp_dir.path().join("c.txt"), "something else").unwrap();
+
+    let mut cmd = Command::new("cargo");
+    cmd.args(["run", "--bin", "deduper-cli", "--", "quarantine"])
+        .arg(temp_dir.path())
+        .arg("--ext").arg("txt")
+        .arg("--algo").arg("xxh3")
+        .arg("--paranoid")
+        .current_dir(env!("CARGO_MANIFEST_DIR"));
+
+    let output = cmd.output().unwrap();
+    assert!(output.status.success());
+
+    let remaining = fs::read_dir(temp_dir.path()).unwrap().count();
+    assert_eq!(remaining, 2);
+}
+
+#[test]
+fn test_quarantine_leaves_all_zero_files() {
+    let temp_dir = TempDir::new().unwrap();
+
+    fs::write(temp_dir.path().join("a.img"), vec![0u8; 4096]).unwrap();
+    fs::write(temp_dir.path().join("b.img"), vec![0u8; 4096]).unwrap();
+    fs::File::create(temp_dir.path().join("c.img"))
+        .unwrap()
+        .set_len(4096)
+        .unwrap();
+
+    let mut cmd = Command::new("cargo");
+    cmd.args(["run", "--bin", "deduper-cli", "--", "quarantine"])
+        .arg(temp_dir.path())
+        .arg("--ext").arg("img")
+        .current_dir(env!("CARGO_MANIFEST_DIR"));
+
+    let output = cmd.output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("All-zero files"));
+    assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 3);
+}
+
+#[test]
+fn test_scan_csv_with_extra_digests() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("file1.txt"), "content1").unwrap();
+
+    let output_file = temp_dir.path().join("report.csv");
+
+    let mut cmd = Command::new("cargo");
+    cmd.args(["run", "--bin", "deduper-cli", "--", "scan"])
+        .arg(temp_dir.path())
+        .arg("--algo").arg("xxh3")
+        .arg("--also").arg("sha256,blake3")
+        .arg("--format").arg("csv")
+        .arg("--output").arg(&output_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"));
+
+    let output = cmd.output().unwrap();
+    assert!(output.status.success());
+
+    let report = fs::read_to_string(&output_file).unwrap();
+    let mut lines = report.lines();
+    assert_eq!(lines.next(), Some("path,xxh3,sha256,blake3"));
+    let row: Vec<_> = lines.next().unwrap().split(',').collect();
+    assert_eq!(row.len(), 4);
+    assert!(row[0].ends_with("file1.txt"));
+    assert_eq!(row[1].len(), 16);
+    assert_eq!(row[2].len(), 64);
+}
+
+#[test]
+fn test_scan_throttled_in_background() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("file1.txt"), vec![1u8; 200_000]).unwrap();
+    fs::write(temp_dir.path().join("file2.txt"), vec![2u8; 200_000]).unwrap();
+
+    let mut cmd = Command::new("cargo");
+    cmd.args(["run", "--bin", "deduper-cli", "--", "scan"])
+        .arg(temp_dir.path())
+        .arg("--max-read-rate").arg("1MB/s")
+        .arg("--priority").arg("background")
+        .current_dir(env!("CARGO_MANIFEST_DIR"));
+
+    let output = cmd.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hashed 2 files"));
+
+    let mut cmd = Command::new("cargo");
+    cmd.args(["run", "--bin", "deduper-cli", "--", "scan"])
+        .arg(temp_dir.path())
+        .arg("--max-read-rate").arg("fast")
+        .current_dir(env!("CARGO_MANIFEST_DIR"));
+    assert!(!cmd.output().unwrap().status.success());
+}
+
+#[test]
+fn test_scan_reports_errors_and_strict_fails() {
+    let temp_dir = TempDir::new().unwrap();
+    let missing = temp_dir.path().join("not-there");
+
+    let mut cmd = Command::new("cargo");
+    cmd.args(["run", "--bin", "deduper-cli", "--", "scan"])
+        .arg(&missing)
+        .current_dir(env!("CARGO_MANIFEST_DIR"));
+    let output = cmd.output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 paths skipped: 1 vanished"));
+
+    let mut cmd = Command::new("cargo");
+    cmd.args(["run", "--bin", "deduper-cli", "--", "scan"])
+        .arg(&missing)
+        .arg("--strict")
+        .current_dir(env!("CARGO_MANIFEST_DIR"));
+    assert!(!cmd.output().unwrap().status.success());
+}
+
+#[test]
+fn test_manifest_export_and_check() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("file1.txt"), "content1").unwrap();
+    fs::write(temp_dir.path().join("file2.txt"), "content2").unwrap();
+    let manifest = temp_dir.path().join("SHA256SUMS");
+
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "deduper-cli", "--", "manifest", "export"])
+        .arg(temp_dir.path())
+        .arg("--output").arg(&manifest)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let text = fs::read_to_string(&manifest).unwrap();
+    assert!(text.contains("  file1.txt\n"));
+
+    fs::write(temp_dir.path().join("file2.txt"), "tampered").unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "deduper-cli", "--", "manifest", "check"])
+        .arg(&manifest)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("file1.txt: OK"));
+    assert!(stdout.contains("file2.txt: FAILED"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_interrupted_scan_still_writes_report() {
+    use std::time::{Duration, Instant};
+
+    let temp_dir = TempDir::new().unwrap();
+    for i in 0..3 {
+        fs::write(temp_dir.path().join(format!("file{}.bin", i)), vec![i; 1 << 20]).unwrap();
+    }
+    let report = temp_dir.path().join("report.json");
+
+    // Run the binary directly so the signal reaches it rather than cargo.
+    let child = Command::new(env!("CARGO_BIN_EXE_deduper-cli"))
+        .arg("scan")
+        .arg(temp_dir.path())
+        .arg("--ext").arg("bin")
+        .arg("--max-read-rate").arg("256KB/s")
+        .arg("--output").arg(&report)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(1500));
+    let start = Instant::now();
+    let killed = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    let output = child.wait_with_output().unwrap();
+
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("results are incomplete"));
+    let entries: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report).unwrap())
+        .unwrap();
+    assert!(entries.as_array().unwrap().len() < 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_quarantine_never_moves_through_symlink() {
+    let temp_dir = TempDir::new().unwrap();
+    let real = temp_dir.path().join("real");
+    fs::create_dir(&real).unwrap();
+    fs::write(real.join("only.txt"), "two copies").unwrap();
+    fs::write(temp_dir.path().join("copy.txt"), "two copies").unwrap();
+    std::os::unix::fs::symlink(&real, temp_dir.path().join("alias")).unwrap();
+
+    let mut cmd = Command::new("cargo");
+    cmd.args(["run", "--bin", "deduper-cli", "--", "quarantine"])
+        .arg(temp_dir.path())
+        .arg("--symlinks").arg("follow")
+        .current_dir(env!("CARGO_MANIFEST_DIR"));
+    let output = cmd.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Reached through a symlink, not quarantined"));
+    // One real copy moved, the other kept; the alias never counted as a third.
+    assert_eq!(stdout.matches("quarantined as").count(), 1);
+    assert!(real.join("only.txt").exists() != temp_dir.path().join("copy.txt").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_quarantine_leaves_hardlinks_alone() {
+    let temp_dir = TempDir::new().unwrap();
+    let a = temp_dir.path().join("a.txt");
+    let b = temp_dir.path().join("b.txt");
+    fs::write(&a, "linked, not copied").unwrap();
+    fs::hard_link(&a, &b).unwrap();
+
+    let mut cmd = Command::new("cargo");
+    cmd.args(["run", "--bin", "deduper-cli", "--", "quarantine"])
+        .arg(temp_dir.path())
+        .current_dir(env!("CARGO_MANIFEST_DIR"));
+    let output = cmd.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Hardlinked, one file on disk"));
+    assert!(stdout.contains("Reclaimable: 0 bytes"));
+    assert!(a.exists() && b.exists());
+}
+
+#[test]
+fn test_quarantine_never_touches_reference_root() {
+    let temp_dir = TempDir::new().unwrap();
+    let downloads = temp_dir.path().join("downloads");
+    let archive = temp_dir.path().join("archive");
+    fs::create_dir(&downloads).unwrap();
+    fs::create_dir(&archive).unwrap();
+    fs::write(downloads.join("report.txt"), "already archived").unwrap();
+    fs::write(archive.join("report-2023.txt"), "already archived").unwrap();
+    fs::write(archive.join("copy-2023.txt"), "already archived").unwrap();
+
+    let mut cmd = Command::new("cargo");
+    cmd.args(["run", "--bin", "deduper-cli", "--", "quarantine"])
+        .arg(&downloads)
+        .arg("--reference").arg(&archive)
+        .current_dir(env!("CARGO_MANIFEST_DIR"));
+    let output = cmd.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    assert!(!downloads.join("report.txt").exists());
+    assert!(archive.join("report-2023.txt").exists());
+    assert!(archive.join("copy-2023.txt").exists());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches("quarantined as").count(), 1);
+}
+
+#[test]
+fn test_excluded_paths_are_left_alone() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    fs::create_dir_all(root.join("node_modules/pkg")).unwrap();
+    fs::create_dir(root.join("build")).unwrap();
+    fs::write(root.join("index.js"), "module.exports = 1;").unwrap();
+    fs::write(root.join("node_modules/pkg/index.js"), "module.exports = 1;").unwrap();
+    fs::write(root.join("build/index.js"), "module.exports = 1;").unwrap();
+    fs::write(root.join(".dedupignore"), "node_modules/\n").unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "deduper-cli", "--", "find"])
+        .arg(root)
+        .args(["--exclude", "/build", "--exclude", ".*"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), [root.join("index.js").to_str().unwrap()]);
+
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "deduper-cli", "--", "quarantine"])
+        .arg(root)
+        .args(["--exclude", "build/"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("quarantined as"));
+    assert!(root.join("node_modules/pkg/index.js").exists());
+    assert!(root.join("build/index.js").exists());
+}
+
+#[test]
+fn test_find_respects_depth_limits() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    fs::create_dir_all(root.join("sub/inner")).unwrap();
+    fs::write(root.join("top.txt"), "1").unwrap();
+    fs::write(root.join("sub/mid.txt"), "2").unwrap();
+    fs::write(root.join("sub/inner/deep.txt"), "3").unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "deduper-cli", "--", "find"])
+        .arg(root)
+        .args(["--min-depth", "2", "--max-depth", "2", "--one-file-system"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), [root.join("sub/mid.txt").to_str().unwrap()]);
+}
+
+#[test]
+fn test_find_hidden_policy() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    fs::create_dir(root.join(".cache")).unwrap();
+    fs::write(root.join(".cache/blob"), "1").unwrap();
+    fs::write(root.join("visible.txt"), "2").unwrap();
+
+    let find = |policy: &str| {
+        let output = Command::new("cargo")
+            .args(["run", "--bin", "deduper-cli", "--", "find"])
+            .arg(root)
+            .args(["--hidden", policy])
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_eq!(find("exclude").trim(), root.join("visible.txt").to_str().unwrap());
+    assert_eq!(find("only").trim(), root.join(".cache/blob").to_str().unwrap());
+    assert_eq!(find("include").lines().count(), 2);
+}
+
+#[test]
+fn test_scan_reports_carry_metadata() {
+    let temp_dir = TempDir::new().unwrap();
+    let out_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("a.txt"), "twice").unwrap();
+    fs::write(temp_dir.path().join("b.txt"), "twice").unwrap();
+
+    let scan = |format: &str, out: &std::path::Path| {
+        let output = Command::new("cargo")
+            .args(["run", "--bin", "deduper-cli", "--", "scan"])
+            .arg(temp_dir.path())
+            .args(["--format", format, "--output"])
+            .arg(out)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        fs::read_to_string(out).unwrap()
+    };
+
+    let json = scan("json", &out_dir.path().join("report.json"));
+    let entries: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(entries[0]["size"], 5);
+    assert!(entries[0]["mtime"].is_string());
+
+    let html = scan("html", &out_dir.path().join("report.html"));
+    assert!(html.contains("2 duplicates (5 bytes each)"));
+    assert!(html.contains("modified "));
+}
+
+#[test]
+fn test_scan_looks_inside_archives() {
+    let temp_dir = TempDir::new().unwrap();
+    let out_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("notes.txt"), "kept in the backup").unwrap();
+    let tar_path = temp_dir.path().join("backup.tar");
+    let status = Command::new("tar")
+        .arg("-cf")
+        .arg(&tar_path)
+        .arg("-C")
+        .arg(temp_dir.path())
+        .arg("notes.txt")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let report = out_dir.path().join("report.json");
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "deduper-cli", "--", "scan", "--archives"])
+        .arg(temp_dir.path())
+        .arg("--output")
+        .arg(&report)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let entries: Vec<serde_json::Value> =
+        serde_json::from_str(&fs::read_to_string(&report).unwrap()).unwrap();
+    // The tar itself fails `--ext txt`; only its member is listed.
+    assert_eq!(entries.len(), 2);
+    let member = entries.iter().find(|e| e["archive"].is_string()).unwrap();
+    assert_eq!(member["path"], format!("{}!/notes.txt", tar_path.display()));
+    assert_eq!(entries[0]["hash"], entries[1]["hash"]);
+}
+
+#[test]
+fn test_quarantine_manifest_needs_paranoid() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("a.txt"), "same").unwrap();
+    fs::write(temp_dir.path().join("b.txt"), "same").unwrap();
+    let manifest = temp_dir.path().join("SHA256SUMS");
+    fs::write(
+        &manifest,
+        "0a47076bd4b9c6a7e5c7f2e4b4bbd6f4b79e1a6bb08d3a5a6e0d5cbe3f3e9f0a  ./a.txt\n",
+    )
+    .unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "deduper-cli", "--", "quarantine"])
+        .arg(temp_dir.path())
+        .arg("--manifest").arg(&manifest)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 3);
+}
+
+#[test]
+fn test_find_strict_fails_on_walk_errors() {
+    let temp_dir = TempDir::new().unwrap();
+    let missing = temp_dir.path().join("missing");
+
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "deduper-cli", "--", "find"])
+        .arg(&missing)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 paths skipped"));
+
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "deduper-cli", "--", "find", "--strict"])
+        .arg(&missing)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_resume_warns_about_xxh3() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("file1.txt"), "same").unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "deduper-cli", "--", "scan", "--resume", "--also", "xxh3"])
+        .arg(temp_dir.path())
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot checkpoint xxh3"));
+
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "deduper-cli", "--", "scan", "--resume"])
+        .arg(temp_dir.path())
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("cannot checkpoint"));
+}
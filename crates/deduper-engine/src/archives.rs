@@ -0,0 +1,342 @@
+//! Members of zip and tar archives as entries of their own.
+//!
+//! Backups often hold the same files that also sit extracted next to them.
+//! Under `ScanOptions::archives`, each supported archive is hashed as usual
+//! and then opened, and every regular file in it becomes a [`FileEntry`]
+//! with a virtual path like `backup.zip!/docs/a.pdf` and `archive` set to
+//! the file it came from. Such entries group with their copies on disk but
+//! are never picked for removal; see [`roots::removable_copies`].
+//! Archives inside archives are not opened.
+//!
+//! [`roots::removable_copies`]: crate::roots::removable_copies
+
+use crate::{
+    cancel::{self, Stop},
+    devices::DevicePools,
+    filtering::Filter,
+    hashing,
+    io::{self, Buffered},
+    metadata::FileMeta,
+    outcome::ScanError,
+    roots::ScanRoot,
+    Candidate, FileEntry, ScanOptions,
+};
+use anyhow::Result;
+use std::{
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+};
+
+/// Joins an archive's path to a member's path inside it.
+pub const MEMBER_SEPARATOR: &str = "!/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveKind {
+    /// The kind of archive `path` is, judging by its name.
+    pub fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(ArchiveKind::TarZst)
+        } else {
+            None
+        }
+    }
+}
+
+/// The virtual path of `member` inside `archive`.
+pub fn member_path(archive: &str, member: &str) -> String {
+    let member = member.trim_start_matches("./").trim_start_matches('/');
+    format!("{archive}{MEMBER_SEPARATOR}{member}")
+}
+
+/// Entries for the members of every archive among `files`, or nothing
+/// unless `opts.archives` is set. An archive that cannot be read yields
+/// the members it got through and an error. Archives are read like any
+/// other file: through `opts.io`, on the pool of their device.
+pub(crate) fn member_entries(
+    files: &[Candidate],
+    roots: &[ScanRoot],
+    filter: &Filter,
+    opts: &ScanOptions,
+    pools: &DevicePools,
+    stop: &Stop,
+) -> Vec<Result<FileEntry, ScanError>> {
+    if !opts.archives {
+        return Vec::new();
+    }
+    let archives: Vec<_> = files
+        .iter()
+        .filter(|c| c.symlink.is_none())
+        .filter_map(|c| Some((c, ArchiveKind::of(&c.path)?)))
+        .collect();
+    pools
+        .par_filter_map(
+            &archives,
+            |(c, _)| c.dev,
+            |&(c, kind)| {
+                let mut out = Vec::new();
+                let mut members = Members {
+                    archive: c,
+                    root: &roots[c.root],
+                    filter,
+                    opts,
+                    stop,
+                    out: &mut out,
+                };
+                if let Err(e) = members.read(kind) {
+                    if !cancel::is_interrupted(&e) {
+                        let err = ScanError::from_anyhow(c.path.clone(), &e);
+                        opts.observer.error(&err);
+                        out.push(Err(err));
+                    }
+                }
+                Some(out)
+            },
+        )
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Collects the entries of one archive's members.
+struct Members<'a> {
+    archive: &'a Candidate,
+    root: &'a ScanRoot,
+    filter: &'a Filter,
+    opts: &'a ScanOptions,
+    stop: &'a Stop,
+    out: &'a mut Vec<Result<FileEntry, ScanError>>,
+}
+
+impl Members<'_> {
+    fn read(&mut self, kind: ArchiveKind) -> Result<()> {
+        let source = io::open(&self.archive.path, &self.opts.io)?;
+        let file = Buffered::new(source, &self.opts.io);
+        match kind {
+            ArchiveKind::Zip => self.read_zip(file),
+            ArchiveKind::Tar => self.read_tar(file),
+            ArchiveKind::TarGz => self.read_tar(flate2::read::GzDecoder::new(file)),
+            ArchiveKind::TarZst => self.read_tar(zstd::Decoder::with_buffer(file)?),
+        }
+    }
+
+    fn read_zip<R: Read + Seek>(&mut self, file: R) -> Result<()> {
+        let mut zip = zip::ZipArchive::new(file)?;
+        for i in 0..zip.len() {
+            let name = zip.name_for_index(i).unwrap_or_default().to_owned();
+            let mut member = match zip.by_index(i) {
+                Ok(m) => m,
+                // Encrypted or oddly compressed; the rest may still read.
+                Err(e) => {
+                    self.failed(&name, &e.into());
+                    continue;
+                }
+            };
+            if !member.is_file() {
+                continue;
+            }
+            let meta = FileMeta {
+                mode: member.unix_mode(),
+                ..Default::default()
+            };
+            self.add(&name, member.size(), meta, &mut member)?;
+        }
+        Ok(())
+    }
+
+    fn read_tar<R: Read>(&mut self, reader: R) -> Result<()> {
+        let mut tar = tar::Archive::new(reader);
+        for member in tar.entries()? {
+            let mut member = member?;
+            let header = member.header();
+            if !header.entry_type().is_file() {
+                continue;
+            }
+            let meta = FileMeta {
+                mtime: header
+                    .mtime()
+                    .ok()
+                    .and_then(|t| chrono::DateTime::from_timestamp(t as i64, 0)),
+                mode: header.mode().ok(),
+                uid: header.uid().ok().map(|u| u as u32),
+                gid: header.gid().ok().map(|g| g as u32),
+                ..Default::default()
+            };
+            let name = member.path()?.to_string_lossy().into_owned();
+            let size = member.size();
+            self.add(&name, size, meta, &mut member)?;
+        }
+        Ok(())
+    }
+
+    /// Hash one member into an entry, if the filter takes it. Only a stop
+    /// is returned as an error; a member that fails to read is recorded.
+    fn add(&mut self, name: &str, size: u64, meta: FileMeta, data: &mut dyn Read) -> Result<()> {
+        let archive = self.archive.path.to_string_lossy();
+        let path = PathBuf::from(member_path(&archive, name));
+        let mtime = meta.mtime.map(Into::into);
+        if let Err(reason) = self.filter.check_parts(size, mtime, &path) {
+            self.opts.observer.file_skipped(&path, reason);
+            return Ok(());
+        }
+        self.opts.observer.file_matched(&path, size);
+
+        let mut algos = vec![self.opts.algo];
+        algos.extend_from_slice(&self.opts.extra_algos);
+        let observer = &*self.opts.observer;
+        let mut hashed = match hashing::hash_stream(data, &algos, observer, self.stop) {
+            Ok(h) => h,
+            Err(e) if cancel::is_interrupted(&e) => return Err(e),
+            Err(e) => {
+                self.failed(name, &e);
+                return Ok(());
+            }
+        };
+        let entry = FileEntry {
+            path: path.to_string_lossy().into_owned(),
+            hash: hashed.digests.remove(0),
+            extra: hashed.digests,
+            all_zero: hashed.all_zero,
+            via_symlink: self.archive.via_symlink,
+            symlink: None,
+            size,
+            inode: None,
+            role: self.root.role,
+            meta,
+            archive: Some(archive.into_owned()),
+        };
+        observer.file_hashed(&entry);
+        self.out.push(Ok(entry));
+        Ok(())
+    }
+
+    fn failed(&mut self, name: &str, err: &anyhow::Error) {
+        let path = member_path(&self.archive.path.to_string_lossy(), name);
+        let err = ScanError::from_anyhow(path.into(), err);
+        self.opts.observer.error(&err);
+        self.out.push(Err(err));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, io::Write};
+    use tempfile::TempDir;
+    use zip::write::SimpleFileOptions;
+
+    #[test]
+    fn test_kind_by_name() {
+        assert_eq!(
+            ArchiveKind::of(Path::new("a/B.ZIP")),
+            Some(ArchiveKind::Zip)
+        );
+        assert_eq!(ArchiveKind::of(Path::new("x.tar")), Some(ArchiveKind::Tar));
+        assert_eq!(
+            ArchiveKind::of(Path::new("x.tgz")),
+            Some(ArchiveKind::TarGz)
+        );
+        assert_eq!(
+            ArchiveKind::of(Path::new("x.tar.zst")),
+            Some(ArchiveKind::TarZst)
+        );
+        assert_eq!(ArchiveKind::of(Path::new("x.gz")), None);
+        assert_eq!(member_path("b.tar", "./docs/a.pdf"), "b.tar!/docs/a.pdf");
+    }
+
+    fn members_of(path: &Path) -> Vec<Result<FileEntry, ScanError>> {
+        let md = std::fs::metadata(path).unwrap();
+        let files = [Candidate::new(path.to_path_buf(), &md)];
+        let roots = [ScanRoot::candidate(path.parent().unwrap())];
+        let opts = ScanOptions {
+            algo: hashing::Algo::Sha256,
+            archives: true,
+            ..Default::default()
+        };
+        let pools = DevicePools::new(&[]);
+        member_entries(
+            &files,
+            &roots,
+            &Filter::any(),
+            &opts,
+            &pools,
+            &Stop::never(),
+        )
+    }
+
+    #[test]
+    fn test_zip_and_tar_members() {
+        let temp_dir = TempDir::new().unwrap();
+        let body = b"inside an archive";
+        let want = hashing::hash_bytes(body, hashing::Algo::Sha256).unwrap();
+
+        let zip_path = temp_dir.path().join("backup.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        zip.add_directory("docs/", SimpleFileOptions::default())
+            .unwrap();
+        zip.start_file("docs/a.txt", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(body).unwrap();
+        zip.finish().unwrap();
+
+        let tgz_path = temp_dir.path().join("backup.tar.gz");
+        let gz = flate2::write::GzEncoder::new(
+            File::create(&tgz_path).unwrap(),
+            flate2::Compression::fast(),
+        );
+        let mut tar = tar::Builder::new(gz);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(body.len() as u64);
+        header.set_mode(0o640);
+        header.set_mtime(1_700_000_000);
+        header.set_cksum();
+        tar.append_data(&mut header, "./docs/a.txt", &body[..])
+            .unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let zst_path = temp_dir.path().join("backup.tar.zst");
+        let zst = zstd::Encoder::new(File::create(&zst_path).unwrap(), 1).unwrap();
+        let mut tar = tar::Builder::new(zst.auto_finish());
+        tar.append_data(&mut header, "docs/a.txt", &body[..])
+            .unwrap();
+        tar.finish().unwrap();
+        drop(tar);
+
+        for path in [&zip_path, &tgz_path, &zst_path] {
+            let members = members_of(path);
+            assert_eq!(members.len(), 1, "{:?}", members);
+            let entry = members[0].as_ref().unwrap();
+            let archive = path.to_string_lossy();
+            assert_eq!(entry.path, format!("{}!/docs/a.txt", archive));
+            assert_eq!(entry.archive.as_deref(), Some(&*archive));
+            assert_eq!(entry.hash, want);
+            assert_eq!(entry.size, body.len() as u64);
+        }
+        let tar_entry = members_of(&tgz_path).remove(0).unwrap();
+        assert_eq!(tar_entry.meta.mode, Some(0o640));
+        assert_eq!(tar_entry.meta.mtime.unwrap().timestamp(), 1_700_000_000);
+    }
+
+    #[test]
+    fn test_corrupt_archive_is_an_error() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("broken.zip");
+        std::fs::write(&path, "not a zip at all").unwrap();
+        let members = members_of(&path);
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].as_ref().unwrap_err().path, path);
+    }
+}
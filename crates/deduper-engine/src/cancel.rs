@@ -0,0 +1,143 @@
+//! Stopping a scan early without losing what it already found.
+//!
+//! A [`CancelToken`] in `ScanOptions::cancel` can be tripped from any thread,
+//! and `ScanOptions::deadline` bounds the wall-clock time of a run. Both are
+//! checked between walked entries, between files and between read blocks, so
+//! a scan winds down within one block per worker. Files cut off midway are
+//! left out rather than reported as errors, and the outcome carries a
+//! [`StopReason`] to say the results are partial. Large files hashed with
+//! checkpoints keep their last saved segment for a resumed run.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+/// Shared flag asking a scan to stop. Clones refer to the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Why a scan ended before covering the whole tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Cancelled,
+    DeadlineReached,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StopReason::Cancelled => "cancelled",
+            StopReason::DeadlineReached => "deadline reached",
+        })
+    }
+}
+
+/// The error a hash returns when it was cut off, so callers can tell it
+/// from a real read failure.
+#[derive(Debug)]
+pub(crate) struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("scan stopped")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// The token and deadline of one scan, remembering whether either actually
+/// cut work short. A deadline passing after the last file does not count.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Stop {
+    token: CancelToken,
+    deadline: Option<Instant>,
+    /// 0 while running, else 1 + the `StopReason` first seen.
+    fired: Arc<AtomicU8>,
+}
+
+impl Stop {
+    pub(crate) fn new(token: &CancelToken, deadline: Option<Instant>) -> Self {
+        Self {
+            token: token.clone(),
+            deadline,
+            fired: Arc::default(),
+        }
+    }
+
+    /// A stop that never fires, for callers outside a scan.
+    pub(crate) fn never() -> Self {
+        Self::default()
+    }
+
+    /// True once the scan should stop, recording why.
+    pub(crate) fn check(&self) -> bool {
+        let reason = if self.token.is_cancelled() {
+            StopReason::Cancelled
+        } else if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            StopReason::DeadlineReached
+        } else {
+            return false;
+        };
+        let code = reason as u8 + 1;
+        let _ = self
+            .fired
+            .compare_exchange(0, code, Ordering::Relaxed, Ordering::Relaxed);
+        true
+    }
+
+    /// Why the scan stopped, if any check fired.
+    pub(crate) fn reason(&self) -> Option<StopReason> {
+        match self.fired.load(Ordering::Relaxed) {
+            0 => None,
+            1 => Some(StopReason::Cancelled),
+            _ => Some(StopReason::DeadlineReached),
+        }
+    }
+}
+
+/// Whether `err` is a hash giving up because of a [`Stop`].
+pub(crate) fn is_interrupted(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Interrupted>().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_stop_records_first_reason() {
+        let token = CancelToken::new();
+        let stop = Stop::new(&token, None);
+        assert!(!stop.check());
+        assert_eq!(stop.reason(), None);
+
+        token.clone().cancel();
+        assert!(stop.check());
+        assert_eq!(stop.reason(), Some(StopReason::Cancelled));
+
+        let past = Instant::now() - Duration::from_millis(1);
+        let stop = Stop::new(&CancelToken::new(), Some(past));
+        assert!(stop.check());
+        assert_eq!(stop.reason(), Some(StopReason::DeadlineReached));
+    }
+}
@@ -0,0 +1,280 @@
+//! Block-level overlap analysis built on content-defined chunks.
+//!
+//! Whole-file hashes only say "identical" or "unrelated". Chunking every file
+//! with [`hashing::chunk_file`] lets us measure how many bytes two files have
+//! in common and how much a chunk-deduplicating store would save overall.
+
+use crate::{filtering::Filter, hashing, outcome::ScanError};
+use anyhow::Result;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+/// The chunk list of a single file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkedFile {
+    pub path: String,
+    pub size: u64,
+    pub chunks: Vec<hashing::Chunk>,
+}
+
+/// Chunks held by more files than this are left out of pair overlaps. A run
+/// of zeros or a common header can sit in thousands of files, and every pair
+/// among them would otherwise be counted.
+pub const MAX_PAIR_OWNERS: usize = 64;
+
+/// Bytes of distinct chunk content two files have in common.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SharedBytes {
+    pub a: String,
+    pub b: String,
+    pub shared: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlockReport {
+    /// Sum of all file sizes.
+    pub total_bytes: u64,
+    /// Bytes left after storing every distinct chunk once.
+    pub unique_bytes: u64,
+    /// `total_bytes / unique_bytes`; 1.0 means nothing is shared.
+    pub dedup_ratio: f64,
+    /// File pairs sharing at least one chunk, largest overlap first. Chunks
+    /// in more than [`MAX_PAIR_OWNERS`] files do not count towards these.
+    pub pairs: Vec<SharedBytes>,
+}
+
+/// The chunked files under a root, with the paths that failed.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkOutcome {
+    pub files: Vec<ChunkedFile>,
+    pub errors: Vec<ScanError>,
+}
+
+/// Chunk every file under `root` accepted by `filter`. Files that cannot be
+/// read are left out; [`chunk_directory_with`] reports them.
+pub fn chunk_directory(
+    root: &Path,
+    filter: &Filter,
+    algo: hashing::Algo,
+    params: hashing::ChunkParams,
+) -> Result<Vec<ChunkedFile>> {
+    let opts = crate::ScanOptions::default();
+    Ok(chunk_directory_with(root, filter, algo, params, &opts)?.files)
+}
+
+/// Like [`chunk_directory`], walking the tree as `opts` says: links,
+/// excludes, depth limits and the like. Its hashing settings are unused.
+/// Paths that could not be walked or chunked are returned alongside.
+pub fn chunk_directory_with(
+    root: &Path,
+    filter: &Filter,
+    algo: hashing::Algo,
+    params: hashing::ChunkParams,
+    opts: &crate::ScanOptions,
+) -> Result<ChunkOutcome> {
+    params.check()?;
+    let (files, mut errors) = crate::matching_files(
+        &[crate::roots::ScanRoot::candidate(root)],
+        filter,
+        opts,
+        &crate::cancel::Stop::never(),
+    );
+    let chunked: Vec<Result<ChunkedFile, ScanError>> = files
+        .par_iter()
+        // Listed links have no content of their own.
+        .filter(|c| c.symlink.is_none() && !c.members_only)
+        .map(|c| match hashing::chunk_file(&c.path, algo, params) {
+            Ok(chunks) => Ok(ChunkedFile {
+                path: c.path.to_string_lossy().into_owned(),
+                size: c.size,
+                chunks,
+            }),
+            Err(e) => Err(ScanError::from_anyhow(c.path.clone(), &e)),
+        })
+        .collect();
+
+    let mut out = Vec::with_capacity(chunked.len());
+    for r in chunked {
+        match r {
+            Ok(f) => out.push(f),
+            Err(e) => errors.push(e),
+        }
+    }
+    Ok(ChunkOutcome { files: out, errors })
+}
+
+/// Work out the tree-wide dedup ratio and the overlap of every file pair.
+///
+/// A chunk counts once per pair no matter how often it repeats inside
+/// either file, and not at all once it is in more than
+/// [`MAX_PAIR_OWNERS`] files.
+pub fn analyze(files: &[ChunkedFile]) -> BlockReport {
+    // chunk hash -> (chunk length, files containing it)
+    let mut owners: HashMap<hashing::Digest, (u64, BTreeSet<usize>)> = HashMap::new();
+    for (i, f) in files.iter().enumerate() {
+        for c in &f.chunks {
+            owners.entry(c.hash).or_insert((c.len, BTreeSet::new())).1.insert(i);
+        }
+    }
+
+    let total_bytes: u64 = files.iter().map(|f| f.size).sum();
+    let unique_bytes: u64 = owners.values().map(|(len, _)| len).sum();
+
+    let mut shared: HashMap<(usize, usize), u64> = HashMap::new();
+    for (len, set) in owners.values() {
+        if set.len() > MAX_PAIR_OWNERS {
+            continue;
+        }
+        let members: Vec<_> = set.iter().copied().collect();
+        for (n, &a) in members.iter().enumerate() {
+            for &b in &members[n + 1..] {
+                *shared.entry((a, b)).or_default() += len;
+            }
+        }
+    }
+
+    let mut pairs: Vec<_> = shared
+        .into_iter()
+        .map(|((a, b), shared)| SharedBytes {
+            a: files[a].path.clone(),
+            b: files[b].path.clone(),
+            shared,
+        })
+        .collect();
+    pairs.sort_by(|x, y| {
+        y.shared
+            .cmp(&x.shared)
+            .then_with(|| x.a.cmp(&y.a))
+            .then_with(|| x.b.cmp(&y.b))
+    });
+
+    BlockReport {
+        total_bytes,
+        unique_bytes,
+        dedup_ratio: if unique_bytes == 0 {
+            1.0
+        } else {
+            total_bytes as f64 / unique_bytes as f64
+        },
+        pairs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Deterministic pseudo-random bytes so chunk boundaries actually occur.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_edited_copy_shares_most_bytes() {
+        let dir = TempDir::new().unwrap();
+        let original = noise(1024 * 1024, 42);
+        let mut edited = b"a few inserted bytes".to_vec();
+        edited.extend_from_slice(&original);
+        fs::write(dir.path().join("image.bin"), &original).unwrap();
+        fs::write(dir.path().join("image-edited.bin"), &edited).unwrap();
+        fs::write(dir.path().join("other.bin"), noise(200 * 1024, 7)).unwrap();
+
+        let files = chunk_directory(
+            dir.path(),
+            &Filter::any(),
+            hashing::Algo::Blake3,
+            hashing::ChunkParams::default(),
+        )
+        .unwrap();
+        let report = analyze(&files);
+
+        assert_eq!(report.pairs.len(), 1);
+        let pair = &report.pairs[0];
+        assert!(pair.a.contains("image") && pair.b.contains("image"));
+        assert!(pair.shared > original.len() as u64 * 3 / 4);
+        assert!(report.dedup_ratio > 1.5);
+    }
+
+    #[test]
+    fn test_widely_shared_chunk_skips_pairs() {
+        let chunk = |n: u8| hashing::Chunk {
+            offset: 0,
+            len: 10,
+            hash: hashing::Digest::from_slice(hashing::Algo::Sha256, &[n; 32]).unwrap(),
+        };
+        let mut files: Vec<ChunkedFile> = (0..=MAX_PAIR_OWNERS)
+            .map(|i| ChunkedFile {
+                path: format!("f{:03}", i),
+                size: 10,
+                chunks: vec![chunk(0)],
+            })
+            .collect();
+        files[0].chunks.push(chunk(1));
+        files[1].chunks.push(chunk(1));
+
+        let report = analyze(&files);
+        assert_eq!(report.unique_bytes, 20);
+        assert_eq!(report.pairs.len(), 1);
+        assert_eq!(report.pairs[0].shared, 10);
+    }
+
+    #[test]
+    fn test_unreadable_file_is_reported() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("ok.bin"), noise(4096, 3)).unwrap();
+        let params = hashing::ChunkParams::default();
+        let opts = crate::ScanOptions::default();
+        let out = chunk_directory_with(
+            &dir.path().join("missing"),
+            &Filter::any(),
+            hashing::Algo::Blake3,
+            params,
+            &opts,
+        )
+        .unwrap();
+        assert!(out.files.is_empty());
+        assert_eq!(out.errors.len(), 1);
+
+        let zero = hashing::ChunkParams {
+            min: 0,
+            avg: 0,
+            max: 0,
+        };
+        let err = chunk_directory_with(
+            dir.path(),
+            &Filter::any(),
+            hashing::Algo::Blake3,
+            zero,
+            &opts,
+        );
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_chunks_cover_whole_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("data.bin");
+        fs::write(&path, noise(300 * 1024, 1)).unwrap();
+
+        let chunks =
+            hashing::chunk_file(&path, hashing::Algo::Xxh3, hashing::ChunkParams::default())
+                .unwrap();
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.iter().map(|c| c.len).sum::<u64>(), 300 * 1024);
+        assert!(chunks.windows(2).all(|w| w[0].offset + w[0].len == w[1].offset));
+    }
+}
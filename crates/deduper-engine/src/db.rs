@@ -1,13 +1,15 @@
-use chrono::{DateTime, Utc};
+use crate::hashing::Digest;
+use crate::resume::Checkpoint;
 use sled::Db;
 use std::path::Path;
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Meta {
     pub mtime: i64,
-    pub hash: String,
+    pub hash: Digest,
 }
 
+#[derive(Clone, Debug)]
 pub struct Index {
     tree: Db,
 }
@@ -26,14 +28,38 @@ impl Index {
             .map(|m| m.mtime == mtime)
             .unwrap_or(false)
     }
-    pub fn upsert(&self, path: &Path, mtime: i64, hash: &str) {
-        let meta = Meta {
-            mtime,
-            hash: hash.to_string(),
-        };
+    pub fn upsert(&self, path: &Path, mtime: i64, hash: Digest) {
+        let meta = Meta { mtime, hash };
         let _ = self.tree.insert(
             path.as_os_str().as_encoded_bytes(),
             bincode::serialize(&meta).unwrap(),
         );
     }
+    /// The last saved hashing progress for `path`, if any.
+    pub fn checkpoint(&self, path: &Path) -> Option<Checkpoint> {
+        self.checkpoints()
+            .ok()?
+            .get(path.as_os_str().as_encoded_bytes())
+            .ok()
+            .flatten()
+            .and_then(|v| bincode::deserialize(&v).ok())
+    }
+    /// Record hashing progress and flush it, so it survives a crash.
+    pub fn save_checkpoint(&self, path: &Path, cp: &Checkpoint) -> sled::Result<()> {
+        let tree = self.checkpoints()?;
+        tree.insert(
+            path.as_os_str().as_encoded_bytes(),
+            bincode::serialize(cp).unwrap(),
+        )?;
+        tree.flush()?;
+        Ok(())
+    }
+    pub fn clear_checkpoint(&self, path: &Path) {
+        if let Ok(tree) = self.checkpoints() {
+            let _ = tree.remove(path.as_os_str().as_encoded_bytes());
+        }
+    }
+    fn checkpoints(&self) -> sled::Result<sled::Tree> {
+        self.tree.open_tree("checkpoints")
+    }
 }
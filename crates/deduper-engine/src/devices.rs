@@ -0,0 +1,208 @@
+//! Per-device concurrency limits.
+//!
+//! One global thread pool makes every worker hit the same spinning disk at
+//! once, and the resulting seeks are slower than reading with one thread.
+//! Files are therefore grouped by `st_dev`; each device gets its own pool
+//! sized for the medium, and the devices are worked on side by side.
+
+use rayon::ThreadPool;
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// The `st_dev` of a file, or 0 where the platform has no such notion.
+pub type DeviceId = u64;
+
+pub fn device_of(md: &fs::Metadata) -> DeviceId {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        md.dev()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = md;
+        0
+    }
+}
+
+/// Readers to use on `dev` when the user did not say: one for rotational
+/// disks, one per CPU for everything else.
+pub fn default_threads(dev: DeviceId) -> usize {
+    if is_rotational(dev) == Some(true) {
+        1
+    } else {
+        rayon::current_num_threads()
+    }
+}
+
+/// Ask sysfs whether the block device behind `dev` spins. `None` if unknown,
+/// e.g. for network or virtual filesystems.
+#[cfg(target_os = "linux")]
+fn is_rotational(dev: DeviceId) -> Option<bool> {
+    let (major, minor) = (libc::major(dev), libc::minor(dev));
+    let node = fs::canonicalize(format!("/sys/dev/block/{}:{}", major, minor)).ok()?;
+    // Partitions have no queue directory of their own; their disk does.
+    [
+        node.join("queue/rotational"),
+        node.join("../queue/rotational"),
+    ]
+    .iter()
+    .find_map(|p| fs::read_to_string(p).ok())
+    .map(|s| s.trim() == "1")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_rotational(_dev: DeviceId) -> Option<bool> {
+    None
+}
+
+/// Turn `(mount point, threads)` overrides into per-device limits.
+///
+/// Paths that cannot be stat'ed are ignored.
+pub(crate) fn resolve_overrides(overrides: &[(PathBuf, usize)]) -> HashMap<DeviceId, usize> {
+    overrides
+        .iter()
+        .filter_map(|(path, n)| Some((device_of(&fs::metadata(path).ok()?), (*n).max(1))))
+        .collect()
+}
+
+/// One thread pool per device, built the first time the device is seen and
+/// reused for the rest of the scan.
+pub(crate) struct DevicePools {
+    limits: HashMap<DeviceId, usize>,
+    pools: Mutex<HashMap<DeviceId, Arc<ThreadPool>>>,
+}
+
+impl DevicePools {
+    /// Pools sized by `(mount point, threads)` overrides, or by
+    /// [`default_threads`] for devices not listed.
+    pub(crate) fn new(overrides: &[(PathBuf, usize)]) -> Self {
+        Self::with_limits(resolve_overrides(overrides))
+    }
+
+    fn with_limits(limits: HashMap<DeviceId, usize>) -> Self {
+        Self {
+            limits,
+            pools: Mutex::default(),
+        }
+    }
+
+    /// The pool for `dev`, or `None` if one could not be built, in which
+    /// case the caller's pool does the work.
+    fn pool(&self, dev: DeviceId) -> Option<Arc<ThreadPool>> {
+        let mut pools = self.pools.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(pool) = pools.get(&dev) {
+            return Some(pool.clone());
+        }
+        let threads = self
+            .limits
+            .get(&dev)
+            .copied()
+            .unwrap_or_else(|| default_threads(dev));
+        let pool = Arc::new(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .ok()?,
+        );
+        pools.insert(dev, pool.clone());
+        Some(pool)
+    }
+
+    /// Run `f` over `items` with at most the configured number of concurrent
+    /// calls per device, all devices at once. Output order is unspecified.
+    pub(crate) fn par_filter_map<T, R, D, F>(&self, items: &[T], dev: D, f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        D: Fn(&T) -> DeviceId,
+        F: Fn(&T) -> Option<R> + Sync,
+    {
+        use rayon::prelude::*;
+
+        let mut by_dev: HashMap<DeviceId, Vec<&T>> = HashMap::new();
+        for item in items {
+            by_dev.entry(dev(item)).or_default().push(item);
+        }
+
+        let f = &f;
+        std::thread::scope(|s| {
+            let workers: Vec<_> = by_dev
+                .into_iter()
+                .map(|(d, group)| {
+                    let pool = self.pool(d);
+                    s.spawn(move || {
+                        let run = || group.par_iter().filter_map(|t| f(t)).collect::<Vec<_>>();
+                        match pool {
+                            Some(pool) => pool.install(run),
+                            None => run(),
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().expect("device worker panicked"))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_limit_caps_concurrency_per_device() {
+        let items: Vec<(DeviceId, u32)> = (0..16).map(|i| (i % 2, i as u32)).collect();
+        let limits = HashMap::from([(0, 1), (1, 3)]);
+        let active = [AtomicUsize::new(0), AtomicUsize::new(0)];
+        let peak = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+        let pools = DevicePools::with_limits(limits);
+        let mut out = pools.par_filter_map(
+            &items,
+            |(d, _)| *d,
+            |(d, v)| {
+                let d = *d as usize;
+                let now = active[d].fetch_add(1, Ordering::SeqCst) + 1;
+                peak[d].fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(5));
+                active[d].fetch_sub(1, Ordering::SeqCst);
+                Some(*v)
+            },
+        );
+
+        out.sort();
+        assert_eq!(out, (0..16).collect::<Vec<_>>());
+        assert_eq!(peak[0].load(Ordering::SeqCst), 1);
+        assert!(peak[1].load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn test_pools_are_reused() {
+        let pools = DevicePools::with_limits(HashMap::from([(7, 1)]));
+        let thread = |_: &u32| Some(std::thread::current().id());
+        let first = pools.par_filter_map(&[7], |d| *d as DeviceId, thread);
+        let second = pools.par_filter_map(&[7], |d| *d as DeviceId, thread);
+        assert_eq!(first, second);
+        assert_eq!(pools.pools.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_resolve_overrides_uses_mount_device() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let dev = device_of(&fs::metadata(dir.path()).unwrap());
+        let limits = resolve_overrides(&[
+            (dir.path().to_path_buf(), 0),
+            ("/definitely/not/here".into(), 4),
+        ]);
+        assert_eq!(limits, HashMap::from([(dev, 1)]));
+    }
+}
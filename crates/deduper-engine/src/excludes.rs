@@ -0,0 +1,166 @@
+//! Pruning the walk with gitignore-style rules.
+//!
+//! Build output, dependency caches and VCS metadata are never worth hashing,
+//! and a pattern in `filtering::Filter` can only drop files one by one after
+//! they were walked. Rules here use `.gitignore` syntax and are checked as
+//! each entry is reached, so an excluded directory is never descended into.
+//!
+//! Rules come from [`IGNORE_FILES`] found in any walked directory and from
+//! patterns given to [`Excludes::new`], which are relative to each scan root
+//! and win over every file. Among files, deeper directories win, and within
+//! one directory a later file in [`IGNORE_FILES`] wins, just as a later line
+//! does. `!pattern` re-includes.
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+
+/// Files read from each walked directory, lowest precedence first.
+pub const IGNORE_FILES: [&str; 3] = [".gitignore", ".ignore", ".dedupignore"];
+
+#[derive(Debug, Clone)]
+pub struct Excludes {
+    /// Patterns given directly, matched against root-relative paths.
+    patterns: Gitignore,
+    read_files: bool,
+}
+
+impl Default for Excludes {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl Excludes {
+    /// Rules from `patterns`, one gitignore line each, plus those from
+    /// [`IGNORE_FILES`] unless `read_files` is false.
+    pub fn new<S: AsRef<str>>(patterns: &[S], read_files: bool) -> anyhow::Result<Self> {
+        let mut builder = GitignoreBuilder::new("");
+        for p in patterns {
+            builder
+                .add_line(None, p.as_ref())
+                .map_err(|e| anyhow::anyhow!("bad exclude pattern {:?}: {e}", p.as_ref()))?;
+        }
+        Ok(Self {
+            patterns: builder.build()?,
+            read_files,
+        })
+    }
+
+    /// No rules at all, not even from files. This is the default, so a
+    /// library scan walks everything unless asked otherwise.
+    pub fn none() -> Self {
+        Self {
+            patterns: Gitignore::empty(),
+            read_files: false,
+        }
+    }
+}
+
+/// Applies [`Excludes`] to one depth-first walk, keeping the rules of the
+/// directories on the current path.
+#[derive(Debug)]
+pub(crate) struct Pruner<'a> {
+    excludes: &'a Excludes,
+    root: &'a Path,
+    /// Rules read from each directory above the current entry, with its depth.
+    dirs: Vec<(usize, Gitignore)>,
+}
+
+impl<'a> Pruner<'a> {
+    pub(crate) fn new(excludes: &'a Excludes, root: &'a Path) -> Self {
+        Self {
+            excludes,
+            root,
+            dirs: Vec::new(),
+        }
+    }
+
+    /// Whether the walked entry at `depth` is excluded, reading the rules of
+    /// each directory it lets through. Must be called for every entry, in
+    /// walk order; the root itself is never excluded.
+    pub(crate) fn excluded(&mut self, path: &Path, depth: usize, is_dir: bool) -> bool {
+        while self.dirs.last().is_some_and(|(d, _)| *d >= depth) {
+            self.dirs.pop();
+        }
+        if depth > 0 && self.matches(path, is_dir) {
+            return true;
+        }
+        if is_dir && self.excludes.read_files {
+            if let Some(rules) = read_rules(path) {
+                self.dirs.push((depth, rules));
+            }
+        }
+        false
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        let relative = path.strip_prefix(self.root).unwrap_or(path);
+        let m = self.excludes.patterns.matched(relative, is_dir);
+        if !m.is_none() {
+            return m.is_ignore();
+        }
+        for (_, rules) in self.dirs.iter().rev() {
+            let m = rules.matched(path, is_dir);
+            if !m.is_none() {
+                return m.is_ignore();
+            }
+        }
+        false
+    }
+}
+
+/// The rules of `dir`'s ignore files, if it has any. Lines that do not
+/// parse are left out, as git does.
+fn read_rules(dir: &Path) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut found = false;
+    for name in IGNORE_FILES {
+        let file = dir.join(name);
+        if file.is_file() {
+            builder.add(file);
+            found = true;
+        }
+    }
+    found.then(|| builder.build().ok()).flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rules_by_precedence() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let sub = root.join("sub");
+        std::fs::create_dir(&sub).unwrap();
+        std::fs::write(root.join(".gitignore"), "*.log\ncache/\n").unwrap();
+        std::fs::write(root.join(".dedupignore"), "!keep.log\n").unwrap();
+        std::fs::write(sub.join(".ignore"), "!debug.log\n").unwrap();
+
+        let excludes = Excludes::new(&["/sub/debug.log"], true).unwrap();
+        let mut pruner = Pruner::new(&excludes, root);
+        assert!(!pruner.excluded(root, 0, true));
+        assert!(pruner.excluded(&root.join("a.log"), 1, false));
+        assert!(!pruner.excluded(&root.join("keep.log"), 1, false));
+        assert!(pruner.excluded(&root.join("cache"), 1, true));
+        assert!(!pruner.excluded(&root.join("cache"), 1, false));
+        assert!(!pruner.excluded(&sub, 1, true));
+        assert!(pruner.excluded(&sub.join("debug.log"), 2, false));
+        assert!(pruner.excluded(&sub.join("b.log"), 2, false));
+
+        // Leaving sub/ drops its rules.
+        let mut pruner = Pruner::new(&excludes, root);
+        pruner.excluded(root, 0, true);
+        pruner.excluded(&sub, 1, true);
+        assert!(pruner.excluded(&root.join("debug.log"), 1, false));
+
+        let none = Excludes::none();
+        let mut pruner = Pruner::new(&none, root);
+        pruner.excluded(root, 0, true);
+        assert!(!pruner.excluded(&root.join("a.log"), 1, false));
+
+        assert!(Excludes::new(&["{"], true).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use chrono::{DateTime, Utc};  // Add this import
 use regex::Regex;
-use std::{fs, path::Path};
+use std::{fs, path::Path, time::SystemTime};
 
 #[derive(Debug, Clone)]
 pub struct Filter {
@@ -11,15 +11,56 @@ pub struct Filter {
     pub since: Option<DateTime<Utc>>,  // Now properly typed
 }
 
+/// Why a walked path did not make it into a scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Devices, sockets and the like.
+    NotAFile,
+    /// A symbolic link, under `SymlinkPolicy::Skip`.
+    Symlink,
+    TooSmall,
+    TooLarge,
+    Extension,
+    Pattern,
+    /// Modified before `since`.
+    TooOld,
+    /// Matched an exclude rule. Reported once for a pruned directory, not
+    /// for each file in it.
+    Excluded,
+    /// Above `ScanOptions::min_depth`.
+    TooShallow,
+    /// Hidden, under `HiddenPolicy::Exclude`. Reported once for a pruned
+    /// directory.
+    Hidden,
+    /// Not hidden, under `HiddenPolicy::Only`.
+    NotHidden,
+}
+
 impl Filter {
     pub fn matches(&self, md: &fs::Metadata, path: &Path) -> bool {
-        if md.len() < self.min_size {
-            return false;
+        self.check(md, path).is_ok()
+    }
+
+    /// Like [`matches`](Self::matches), naming the first criterion that fails.
+    pub fn check(&self, md: &fs::Metadata, path: &Path) -> Result<(), SkipReason> {
+        self.check_parts(md.len(), md.modified().ok(), path)
+    }
+
+    /// [`check`](Self::check) for data with no metadata of its own, such as
+    /// an archive member, given its length and modification time if known.
+    pub fn check_parts(
+        &self,
+        len: u64,
+        mtime: Option<SystemTime>,
+        path: &Path,
+    ) -> Result<(), SkipReason> {
+        if len < self.min_size {
+            return Err(SkipReason::TooSmall);
         }
         
         if let Some(max) = self.max_size {
-            if md.len() > max {
-                return false;
+            if len > max {
+                return Err(SkipReason::TooLarge);
             }
         }
         
@@ -28,23 +69,37 @@ impl Filter {
                 .and_then(|e| e.to_str())
                 .map(|e| e != wanted)
                 .unwrap_or(true) {
-                return false;
+                return Err(SkipReason::Extension);
             }
         }
         
         if !self.pattern.is_match(&path.to_string_lossy()) {
-            return false;
+            return Err(SkipReason::Pattern);
         }
         
         if let Some(since) = self.since {
-            if let Ok(mtime) = md.modified() {
+            if let Some(mtime) = mtime {
                 if mtime < since.into() {
-                    return false;
+                    return Err(SkipReason::TooOld);
                 }
             }
         }
         
-        true
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Filter {
+    /// Takes every file, whatever its size, name or age.
+    pub(crate) fn any() -> Self {
+        Self {
+            min_size: 0,
+            max_size: None,
+            ext: None,
+            pattern: Regex::new(".*").unwrap(),
+            since: None,
+        }
     }
 }
 
@@ -0,0 +1,105 @@
+//! Paths that are one file on disk.
+//!
+//! Hardlinks to the same inode hash the same, but removing one of them frees
+//! nothing. Entries record their [`Inode`], so duplicate groups can be split
+//! into physical copies and space counted only for copies that would really
+//! go away.
+
+use crate::FileEntry;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Inode {
+    pub dev: u64,
+    pub ino: u64,
+    /// Number of paths to the inode, scanned or not.
+    pub nlink: u64,
+}
+
+impl Inode {
+    fn key(&self) -> (u64, u64) {
+        (self.dev, self.ino)
+    }
+}
+
+/// Split entries into physical files, each with every path among `entries`
+/// that leads to it. Files keep the order in which they first appear, and
+/// entries without a known inode stand alone.
+pub fn physical_copies<'a>(entries: &[&'a FileEntry]) -> Vec<Vec<&'a FileEntry>> {
+    let mut index: HashMap<(u64, u64), usize> = HashMap::new();
+    let mut copies: Vec<Vec<&FileEntry>> = Vec::new();
+    for &e in entries {
+        let slot = match e.inode {
+            Some(inode) => *index.entry(inode.key()).or_insert(copies.len()),
+            None => copies.len(),
+        };
+        if slot == copies.len() {
+            copies.push(Vec::new());
+        }
+        copies[slot].push(e);
+    }
+    copies
+}
+
+/// Files reached by two or more of the scanned paths.
+pub fn hardlink_sets(entries: &[FileEntry]) -> Vec<Vec<&FileEntry>> {
+    let all: Vec<&FileEntry> = entries.iter().collect();
+    let mut sets = physical_copies(&all);
+    sets.retain(|s| s.len() > 1);
+    sets
+}
+
+/// Bytes freed by removing every path of each group's
+/// [`removable_copies`](crate::roots::removable_copies). A copy counts only
+/// if all of its links were scanned, since a link elsewhere keeps its data
+/// on disk.
+pub fn reclaimable_bytes(groups: &[Vec<&FileEntry>]) -> u64 {
+    groups
+        .iter()
+        .flat_map(|g| crate::roots::removable_copies(g))
+        .filter(|paths| paths[0].inode.is_none_or(|i| paths.len() as u64 >= i.nlink))
+        .map(|paths| paths[0].size)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, ino: Option<(u64, u64)>) -> FileEntry {
+        FileEntry {
+            size: 100,
+            inode: ino.map(|(ino, nlink)| Inode { dev: 1, ino, nlink }),
+            ..FileEntry::test(path, "xxh3:0000000000000001")
+        }
+    }
+
+    #[test]
+    fn test_copies_and_space() {
+        let entries = [
+            entry("a", Some((1, 2))),
+            entry("b", Some((2, 1))),
+            entry("a2", Some((1, 2))),
+            entry("c", Some((3, 2))),
+            entry("d", None),
+        ];
+        let group: Vec<&FileEntry> = entries.iter().collect();
+
+        let copies = physical_copies(&group);
+        let paths: Vec<Vec<&str>> = copies
+            .iter()
+            .map(|c| c.iter().map(|e| e.path.as_str()).collect())
+            .collect();
+        assert_eq!(paths, [vec!["a", "a2"], vec!["b"], vec!["c"], vec!["d"]]);
+
+        let sets = hardlink_sets(&entries);
+        assert_eq!(sets.len(), 1);
+        assert_eq!(sets[0].len(), 2);
+
+        // Keeping a: b and d free their bytes, c has a link outside the scan.
+        assert_eq!(reclaimable_bytes(&[group]), 200);
+        let only_links = vec![&entries[0], &entries[2]];
+        assert_eq!(reclaimable_bytes(&[only_links]), 0);
+    }
+}
@@ -1,43 +1,502 @@
-use anyhow::Result;
+use crate::{
+    cancel::{Interrupted, Stop},
+    io::{CachePolicy, ReadOptions, Source, DIRECT_ALIGN},
+    observer::{NoopObserver, ScanObserver},
+};
+use anyhow::{bail, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Digest as ShaDigest, Sha256};
 use std::{
+    fmt,
     fs::File,
     io::{BufReader, Read},
     path::Path,
+    str::FromStr,
 };
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Algo {
     Sha256,
     Blake3,
     Xxh3,
 }
 
-pub fn hash_file(path: &Path, algo: Algo) -> Result<String> {
-    let mut reader = BufReader::new(File::open(path)?);
-    match algo {
-        Algo::Sha256 => {
-            let mut hasher = Sha256::new();
-            pipe(&mut reader, |buf| { hasher.update(buf); })?;
-            Ok(format!("{:x}", hasher.finalize()))
+impl Algo {
+    /// Length of this algorithm's digest in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            Algo::Sha256 | Algo::Blake3 => 32,
+            Algo::Xxh3 => 8,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algo::Sha256 => "sha256",
+            Algo::Blake3 => "blake3",
+            Algo::Xxh3 => "xxh3",
+        }
+    }
+}
+
+impl fmt::Display for Algo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Algo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha256" => Ok(Algo::Sha256),
+            "blake3" => Ok(Algo::Blake3),
+            "xxh3" => Ok(Algo::Xxh3),
+            other => bail!("unknown hash algorithm {:?}", other),
+        }
+    }
+}
+
+/// A binary digest tagged with the algorithm that produced it.
+///
+/// Written as `algo:hex`, e.g. `blake3:ab12…`. Digests from different
+/// algorithms never compare equal, even if their bytes happen to match.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Digest {
+    algo: Algo,
+    bytes: [u8; 32],
+}
+
+impl Digest {
+    /// Build a digest from raw bytes; `bytes` must be exactly `algo.digest_len()` long.
+    pub fn from_slice(algo: Algo, bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != algo.digest_len() {
+            bail!(
+                "{} digest must be {} bytes, got {}",
+                algo,
+                algo.digest_len(),
+                bytes.len()
+            );
+        }
+        let mut buf = [0u8; 32];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        Ok(Self { algo, bytes: buf })
+    }
+
+    pub fn from_hex(algo: Algo, hex: &str) -> Result<Self> {
+        Self::from_slice(algo, &hex::decode(hex)?)
+    }
+
+    pub fn algo(&self) -> Algo {
+        self.algo
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.algo.digest_len()]
+    }
+
+    /// Lowercase hex of the digest bytes, without the algorithm prefix.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.as_bytes())
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algo, self.to_hex())
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Digest({})", self)
+    }
+}
+
+impl FromStr for Digest {
+    type Err = anyhow::Error;
+
+    /// Parses `algo:hex`. Bare hex from reports written before digests were
+    /// tagged is accepted too: 16 digits are read as xxh3 and 64 digits as
+    /// sha256, the CLI default then and the only algorithm quarantine used.
+    /// A 64-digit blake3 digest from those days must be read with
+    /// [`Digest::from_hex`] instead.
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((algo, hex)) = s.split_once(':') {
+            return Self::from_hex(algo.parse()?, hex);
+        }
+        match s.len() {
+            16 => Self::from_hex(Algo::Xxh3, s),
+            64 => Self::from_hex(Algo::Sha256, s),
+            _ => bail!("cannot tell which algorithm produced {:?}", s),
+        }
+    }
+}
+
+impl Serialize for Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+pub fn hash_file(path: &Path, algo: Algo) -> Result<Digest> {
+    hash_reader(&mut BufReader::new(File::open(path)?), algo)
+}
+
+/// What one pass over a file with [`hash_file_multi`] found out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileHash {
+    /// One digest per requested algorithm, in the same order.
+    pub digests: Vec<Digest>,
+    /// Every byte was zero, including an empty file.
+    pub all_zero: bool,
+}
+
+/// Hash a file with several algorithms while reading it only once.
+///
+/// `io` controls read size and how the page cache is treated. Holes in
+/// sparse files are hashed as the zeros they read as, without reading them.
+/// Files above `io.parallel_above` are hashed from a memory map instead,
+/// giving the same digests.
+pub fn hash_file_multi(path: &Path, algos: &[Algo], io: &ReadOptions) -> Result<FileHash> {
+    hash_file_observed(path, algos, io, &NoopObserver, &Stop::never())
+}
+
+/// [`hash_file_multi`], telling `observer` about every block hashed and
+/// giving up with [`Interrupted`] between blocks once `stop` fires.
+pub(crate) fn hash_file_observed(
+    path: &Path,
+    algos: &[Algo],
+    io: &ReadOptions,
+    observer: &dyn ScanObserver,
+    stop: &Stop,
+) -> Result<FileHash> {
+    if let Some(min) = io.parallel_above {
+        if io.cache == CachePolicy::Normal && io.throttle.is_none() {
+            let file = File::open(path)?;
+            let len = file.metadata()?.len();
+            if len > 0 && len >= min {
+                return hash_mapped(&file, algos, observer, stop);
+            }
+        }
+    }
+    let mut hashers: Vec<_> = algos.iter().map(|&a| Hasher::new(a)).collect();
+    let mut buf = io.buffer();
+    let buf = buf.as_mut_slice();
+    let mut source = crate::io::open(path, io)?;
+    loop {
+        if stop.check() {
+            return Err(Interrupted.into());
+        }
+        let n = source.read(buf)?;
+        if n == 0 {
+            break;
+        }
+        for h in &mut hashers {
+            h.update(&buf[..n]);
+        }
+        observer.bytes_hashed(n as u64);
+    }
+    Ok(FileHash {
+        digests: hashers
+            .into_iter()
+            .map(Hasher::finish)
+            .collect::<Result<_>>()?,
+        all_zero: source.all_zero(),
+    })
+}
+
+/// Whether every byte of the file is zero. Reading stops at the first
+/// nonzero block, and holes in sparse files are not read at all.
+pub(crate) fn is_all_zero(path: &Path, io: &ReadOptions) -> Result<bool> {
+    let mut buf = io.buffer();
+    let buf = buf.as_mut_slice();
+    let mut source = crate::io::open(path, io)?;
+    while source.all_zero() {
+        if source.read(buf)? == 0 {
+            break;
+        }
+    }
+    Ok(source.all_zero())
+}
+
+/// Hash everything `reader` yields with each of `algos` in one pass, for
+/// data that is not a file of its own, such as an archive member. Reports
+/// and stops like [`hash_file_observed`].
+pub(crate) fn hash_stream<R: Read + ?Sized>(
+    reader: &mut R,
+    algos: &[Algo],
+    observer: &dyn ScanObserver,
+    stop: &Stop,
+) -> Result<FileHash> {
+    let mut hashers: Vec<_> = algos.iter().map(|&a| Hasher::new(a)).collect();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut all_zero = true;
+    loop {
+        if stop.check() {
+            return Err(Interrupted.into());
+        }
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        for h in &mut hashers {
+            h.update(&buf[..n]);
+        }
+        all_zero &= buf[..n].iter().all(|&b| b == 0);
+        observer.bytes_hashed(n as u64);
+    }
+    Ok(FileHash {
+        digests: hashers
+            .into_iter()
+            .map(Hasher::finish)
+            .collect::<Result<_>>()?,
+        all_zero,
+    })
+}
+
+/// Stretch of a mapped file hashed between two looks at `stop`.
+const MAPPED_CHUNK: usize = 64 * 1024 * 1024;
+
+/// Hash a mapped file using every core: BLAKE3 splits its tree across the
+/// rayon pool, and the inherently sequential algorithms each get a thread
+/// of their own, running alongside the all-zero check.
+fn hash_mapped(
+    file: &File,
+    algos: &[Algo],
+    observer: &dyn ScanObserver,
+    stop: &Stop,
+) -> Result<FileHash> {
+    // SAFETY: the map is private to this call and only read. As with any
+    // mapping, another process truncating the file meanwhile can fault.
+    let map = unsafe { memmap2::Mmap::map(file)? };
+    #[cfg(unix)]
+    let _ = map.advise(memmap2::Advice::Sequential);
+
+    let mut hashers: Vec<_> = algos.iter().map(|&a| Hasher::new(a)).collect();
+    let mut nonzero = false;
+    for data in map.chunks(MAPPED_CHUNK) {
+        if stop.check() {
+            return Err(Interrupted.into());
+        }
+        let ((), found) = rayon::join(
+            || hashers.par_iter_mut().for_each(|h| h.update_rayon(data)),
+            || !nonzero && data.par_chunks(1 << 20).any(|c| c.iter().any(|&b| b != 0)),
+        );
+        nonzero |= found;
+        observer.bytes_hashed(data.len() as u64);
+    }
+    Ok(FileHash {
+        digests: hashers
+            .into_iter()
+            .map(Hasher::finish)
+            .collect::<Result<_>>()?,
+        all_zero: !nonzero,
+    })
+}
+
+/// Hash only the first and last `window` bytes of a file.
+///
+/// Files no larger than `2 * window` are hashed in full. The result is only
+/// meaningful when compared against files of the same size.
+pub fn hash_partial(path: &Path, algo: Algo, window: u64) -> Result<Digest> {
+    hash_partial_with(path, algo, window, &ReadOptions::default())
+}
+
+/// [`hash_partial`], reading through `io` so its cache policy and throttle
+/// apply. Only the two windows are read, give or take alignment.
+pub fn hash_partial_with(path: &Path, algo: Algo, window: u64, io: &ReadOptions) -> Result<Digest> {
+    let opts = ReadOptions {
+        buf_size: window as usize,
+        ..io.clone()
+    };
+    let mut buf = opts.buffer();
+    let buf = buf.as_mut_slice();
+    let mut source = crate::io::open(path, &opts)?;
+    let len = source.len();
+    let mut sample = Vec::with_capacity(window as usize * 2);
+    if len <= window * 2 {
+        read_up_to(&mut source, buf, len as usize, &mut sample)?;
+        return hash_reader(&mut sample.as_slice(), algo);
+    }
+    read_up_to(&mut source, buf, window as usize, &mut sample)?;
+    let tail = len - window;
+    let aligned = tail - tail % DIRECT_ALIGN as u64;
+    source.seek_to(aligned)?;
+    let mut rest = Vec::with_capacity((len - aligned) as usize);
+    read_up_to(&mut source, buf, (len - aligned) as usize, &mut rest)?;
+    sample.extend_from_slice(&rest[(tail - aligned) as usize..]);
+    hash_reader(&mut sample.as_slice(), algo)
+}
+
+/// Append up to `want` more bytes from `source` to `out`, stopping early at
+/// the end of the file.
+fn read_up_to(source: &mut Source, buf: &mut [u8], want: usize, out: &mut Vec<u8>) -> Result<()> {
+    let end = out.len() + want;
+    while out.len() < end {
+        let n = source.read(buf)?;
+        if n == 0 {
+            break;
         }
-        Algo::Blake3 => {
-            let mut hasher = blake3::Hasher::new();
-            pipe(&mut reader, |buf| { hasher.update(buf); })?;
-            Ok(hasher.finalize().to_hex().to_string())
+        out.extend_from_slice(&buf[..n.min(end - out.len())]);
+    }
+    Ok(())
+}
+
+/// Size bounds for content-defined chunking, in bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkParams {
+    pub min: u32,
+    pub avg: u32,
+    pub max: u32,
+}
+
+impl Default for ChunkParams {
+    fn default() -> Self {
+        Self {
+            min: 16 * 1024,
+            avg: 64 * 1024,
+            max: 256 * 1024,
         }
-        Algo::Xxh3 => {
-            let mut hasher = xxhash_rust::xxh3::Xxh3::new();
-            pipe(&mut reader, |buf| { hasher.update(buf); })?;
-            Ok(format!("{:016x}", hasher.digest()))
+    }
+}
+
+impl ChunkParams {
+    /// Fails unless every bound is within what FastCDC accepts and
+    /// `min <= avg <= max`.
+    pub fn check(&self) -> Result<()> {
+        use fastcdc::v2020::{
+            AVERAGE_MAX, AVERAGE_MIN, MAXIMUM_MAX, MAXIMUM_MIN, MINIMUM_MAX, MINIMUM_MIN,
+        };
+        let bounds = [
+            ("minimum", self.min, MINIMUM_MIN, MINIMUM_MAX),
+            ("average", self.avg, AVERAGE_MIN, AVERAGE_MAX),
+            ("maximum", self.max, MAXIMUM_MIN, MAXIMUM_MAX),
+        ];
+        for (name, size, lo, hi) in bounds {
+            if !(lo..=hi).contains(&size) {
+                bail!(
+                    "{} chunk size {} is not between {} and {} bytes",
+                    name,
+                    size,
+                    lo,
+                    hi
+                );
+            }
+        }
+        if !(self.min <= self.avg && self.avg <= self.max) {
+            bail!(
+                "chunk sizes must satisfy min <= avg <= max, got {}/{}/{}",
+                self.min,
+                self.avg,
+                self.max
+            );
         }
+        Ok(())
     }
 }
 
-fn pipe<R: Read, F: FnMut(&[u8])>(r: &mut R, mut feed: F) -> Result<()> {
-    let mut buf = [0u8; 8192];
+/// One content-defined chunk of a file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    pub offset: u64,
+    pub len: u64,
+    pub hash: Digest,
+}
+
+/// Split a file into FastCDC chunks and hash each one with `algo`.
+///
+/// Cut points depend only on content, so an insertion near the start of a
+/// file shifts offsets but leaves most later chunks unchanged.
+pub fn chunk_file(path: &Path, algo: Algo, params: ChunkParams) -> Result<Vec<Chunk>> {
+    params.check()?;
+    let file = File::open(path)?;
+    let mut chunks = Vec::new();
+    for c in fastcdc::v2020::StreamCDC::new(file, params.min, params.avg, params.max) {
+        let c = c?;
+        chunks.push(Chunk {
+            offset: c.offset,
+            len: c.length as u64,
+            hash: hash_reader(&mut c.data.as_slice(), algo)?,
+        });
+    }
+    Ok(chunks)
+}
+
+/// Digest of an in-memory buffer.
+pub(crate) fn hash_bytes(data: &[u8], algo: Algo) -> Result<Digest> {
+    let mut hasher = Hasher::new(algo);
+    hasher.update(data);
+    hasher.finish()
+}
+
+fn hash_reader<R: Read>(reader: &mut R, algo: Algo) -> Result<Digest> {
+    let mut hasher = Hasher::new(algo);
+    pipe(reader, |buf| hasher.update(buf))?;
+    hasher.finish()
+}
+
+/// A running hash of any supported algorithm.
+enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl Hasher {
+    fn new(algo: Algo) -> Self {
+        match algo {
+            Algo::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algo::Blake3 => Hasher::Blake3(Box::default()),
+            Algo::Xxh3 => Hasher::Xxh3(Box::default()),
+        }
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(buf),
+            Hasher::Blake3(h) => {
+                h.update(buf);
+            }
+            Hasher::Xxh3(h) => h.update(buf),
+        }
+    }
+
+    /// Like `update`, spreading BLAKE3 over the rayon pool.
+    fn update_rayon(&mut self, buf: &[u8]) {
+        match self {
+            Hasher::Blake3(h) => {
+                h.update_rayon(buf);
+            }
+            _ => self.update(buf),
+        }
+    }
+
+    fn finish(self) -> Result<Digest> {
+        match self {
+            Hasher::Sha256(h) => Digest::from_slice(Algo::Sha256, &h.finalize()),
+            Hasher::Blake3(h) => Digest::from_slice(Algo::Blake3, h.finalize().as_bytes()),
+            Hasher::Xxh3(h) => Digest::from_slice(Algo::Xxh3, &h.digest().to_be_bytes()),
+        }
+    }
+}
+
+fn pipe<R: Read, F: FnMut(&[u8])>(r: &mut R, feed: F) -> Result<()> {
+    pipe_with(r, &mut [0u8; 8192], feed)
+}
+
+fn pipe_with<R: Read, F: FnMut(&[u8])>(r: &mut R, buf: &mut [u8], mut feed: F) -> Result<()> {
     loop {
-        let n = r.read(&mut buf)?;
+        let n = r.read(buf)?;
         if n == 0 {
             break;
         }
@@ -49,6 +508,7 @@ fn pipe<R: Read, F: FnMut(&[u8])>(r: &mut R, mut feed: F) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::io::CachePolicy;
     use tempfile::NamedTempFile;
     use std::io::Write;
 
@@ -59,7 +519,7 @@ mod tests {
         
         let hash = hash_file(temp_file.path(), Algo::Sha256).unwrap();
         // Correct SHA256 hash for "Hello World\n" (with newline)
-        assert_eq!(hash, "d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26");
+        assert_eq!(hash.to_hex(), "d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26");
     }
 
     // ... rest of your tests remain the same
@@ -72,8 +532,8 @@ mod tests {
         writeln!(temp_file, "Hello World").unwrap();
         
         let hash = hash_file(temp_file.path(), Algo::Blake3).unwrap();
-        assert!(!hash.is_empty());
-        assert_eq!(hash.len(), 64); // BLAKE3 produces 64-char hex string
+        assert_eq!(hash.algo(), Algo::Blake3);
+        assert_eq!(hash.to_hex().len(), 64); // BLAKE3 produces 64-char hex string
     }
 
     #[test]
@@ -82,8 +542,8 @@ mod tests {
         writeln!(temp_file, "Hello World").unwrap();
         
         let hash = hash_file(temp_file.path(), Algo::Xxh3).unwrap();
-        assert!(!hash.is_empty());
-        assert_eq!(hash.len(), 16); // XXH3 produces 16-char hex string
+        assert_eq!(hash.algo(), Algo::Xxh3);
+        assert_eq!(hash.to_hex().len(), 16); // XXH3 produces 16-char hex string
     }
 
     #[test]
@@ -116,4 +576,163 @@ mod tests {
         assert_ne!(hash1, hash2);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_digest_round_trip() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "Hello World").unwrap();
+
+        let hash = hash_file(temp_file.path(), Algo::Blake3).unwrap();
+        let text = hash.to_string();
+        assert!(text.starts_with("blake3:"));
+        assert_eq!(text.parse::<Digest>().unwrap(), hash);
+
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, format!("\"{}\"", text));
+        assert_eq!(serde_json::from_str::<Digest>(&json).unwrap(), hash);
+    }
+
+    #[test]
+    fn test_digest_reads_legacy_hex() {
+        // A report as the untagged sha256 scan wrote it.
+        let hex = "d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26";
+        let json = format!("[{{\"path\":\"a.txt\",\"hash\":\"{}\"}}]", hex);
+        let report: Vec<crate::FileEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(report[0].hash.algo(), Algo::Sha256);
+        assert_eq!(report[0].hash.to_string(), format!("sha256:{}", hex));
+
+        let xxh: Digest = "00000000000000ff".parse().unwrap();
+        assert_eq!(xxh.algo(), Algo::Xxh3);
+        assert_eq!(xxh.as_bytes(), &[0, 0, 0, 0, 0, 0, 0, 0xff]);
+
+        assert!("abc".parse::<Digest>().is_err());
+        assert!("md5:00".parse::<Digest>().is_err());
+    }
+
+    #[test]
+    fn test_multi_digest_matches_single() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "Hello World").unwrap();
+
+        let algos = [Algo::Xxh3, Algo::Sha256, Algo::Blake3];
+        let all = hash_file_multi(temp_file.path(), &algos, &ReadOptions::default())
+            .unwrap()
+            .digests;
+        assert_eq!(all.len(), 3);
+        for (algo, digest) in algos.iter().zip(&all) {
+            assert_eq!(*digest, hash_file(temp_file.path(), *algo).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_read_options_do_not_change_digest() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(&vec![9u8; 70_000]).unwrap();
+
+        let expected = hash_file(temp_file.path(), Algo::Blake3).unwrap();
+        for cache in [CachePolicy::Normal, CachePolicy::DropBehind, CachePolicy::Direct] {
+            let io = ReadOptions {
+                buf_size: 1 << 20,
+                cache,
+                ..Default::default()
+            };
+            let got = hash_file_multi(temp_file.path(), &[Algo::Blake3], &io).unwrap();
+            assert_eq!(got.digests, vec![expected]);
+            assert!(!got.all_zero);
+        }
+    }
+
+    #[test]
+    fn test_mapped_digest_matches_streaming() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        let data: Vec<u8> = (0..3_000_000u32).map(|i| (i * 7 % 253) as u8).collect();
+        temp_file.write_all(&data).unwrap();
+
+        let algos = [Algo::Blake3, Algo::Sha256, Algo::Xxh3];
+        let streamed = hash_file_multi(temp_file.path(), &algos, &ReadOptions::default()).unwrap();
+        let io = ReadOptions {
+            parallel_above: Some(1024),
+            ..Default::default()
+        };
+        let mapped = hash_file_multi(temp_file.path(), &algos, &io).unwrap();
+        assert_eq!(mapped, streamed);
+        assert!(!mapped.all_zero);
+
+        let zeros = NamedTempFile::new().unwrap();
+        zeros.as_file().set_len(2_000_000).unwrap();
+        let mapped = hash_file_multi(zeros.path(), &algos, &io).unwrap();
+        assert_eq!(mapped, hash_file_multi(zeros.path(), &algos, &Default::default()).unwrap());
+        assert!(mapped.all_zero);
+    }
+
+    #[test]
+    fn test_mapped_hashing_stops() {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().set_len(1_000_000).unwrap();
+        let io = ReadOptions {
+            parallel_above: Some(1024),
+            ..Default::default()
+        };
+        let token = crate::cancel::CancelToken::new();
+        token.cancel();
+        let stop = Stop::new(&token, None);
+        let err = hash_file_observed(temp_file.path(), &[Algo::Sha256], &io, &NoopObserver, &stop)
+            .unwrap_err();
+        assert!(crate::cancel::is_interrupted(&err));
+    }
+
+    #[test]
+    fn test_sparse_file_hashes_like_dense_copy() {
+        let sparse = NamedTempFile::new().unwrap();
+        sparse.as_file().set_len(8 * 1024 * 1024).unwrap();
+        let mut dense = NamedTempFile::new().unwrap();
+        dense.write_all(&vec![0u8; 8 * 1024 * 1024]).unwrap();
+
+        let io = ReadOptions::default();
+        let a = hash_file_multi(sparse.path(), &[Algo::Sha256, Algo::Xxh3], &io).unwrap();
+        let b = hash_file_multi(dense.path(), &[Algo::Sha256, Algo::Xxh3], &io).unwrap();
+        assert_eq!(a, b);
+        assert!(a.all_zero);
+        assert_eq!(a.digests[0], hash_file(sparse.path(), Algo::Sha256).unwrap());
+    }
+
+    #[test]
+    fn test_digests_of_different_algos_differ() {
+        let a = Digest::from_slice(Algo::Sha256, &[1; 32]).unwrap();
+        let b = Digest::from_slice(Algo::Blake3, &[1; 32]).unwrap();
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_all_zero_check() {
+        let io = ReadOptions::default();
+        let zeros = NamedTempFile::new().unwrap();
+        zeros.as_file().set_len(300_000).unwrap();
+        assert!(is_all_zero(zeros.path(), &io).unwrap());
+        let mut data = NamedTempFile::new().unwrap();
+        data.write_all(&[0, 0, 7]).unwrap();
+        assert!(!is_all_zero(data.path(), &io).unwrap());
+    }
+
+    #[test]
+    fn test_chunk_params_are_checked() {
+        assert!(ChunkParams::default().check().is_ok());
+        let zero = ChunkParams {
+            min: 0,
+            avg: 0,
+            max: 0,
+        };
+        assert!(zero.check().is_err());
+        let huge = ChunkParams {
+            min: 2 * 1024 * 1024,
+            avg: 8 * 1024 * 1024,
+            max: 32 * 1024 * 1024,
+        };
+        assert!(huge.check().is_err());
+        let backwards = ChunkParams {
+            min: 64 * 1024,
+            avg: 16 * 1024,
+            max: 256 * 1024,
+        };
+        assert!(backwards.check().is_err());
+    }
+}
@@ -0,0 +1,62 @@
+//! What a scan does with hidden files.
+//!
+//! A path counts as hidden when [`deduper_utils::file_utils::is_hidden`] says
+//! so: a leading dot in its name, or a hidden mark such as the `user.hidden`
+//! xattr. The roots themselves are never judged, so scanning `~/.cache`
+//! directly works under any policy.
+
+use deduper_utils::file_utils::is_hidden;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HiddenPolicy {
+    /// Scan hidden files like any other.
+    #[default]
+    Include,
+    /// Leave out hidden files, and do not descend into hidden directories.
+    Exclude,
+    /// Scan only hidden files and whatever lies in hidden directories.
+    Only,
+}
+
+/// Tracks, during a depth-first walk, whether the current entry is hidden or
+/// lies in a hidden directory below the root.
+#[derive(Debug, Default)]
+pub(crate) struct HiddenDepth {
+    /// Depth of the outermost hidden directory being walked, if any.
+    hidden_at: Option<usize>,
+}
+
+impl HiddenDepth {
+    /// Whether the walked entry at `depth` is hidden, itself or through an
+    /// ancestor. Must be called for every entry, in walk order.
+    pub(crate) fn visit(&mut self, path: &Path, depth: usize, is_dir: bool) -> bool {
+        if self.hidden_at.is_some_and(|d| depth <= d) {
+            self.hidden_at = None;
+        }
+        if self.hidden_at.is_some() {
+            return true;
+        }
+        let hidden = depth > 0 && is_hidden(path);
+        if hidden && is_dir {
+            self.hidden_at = Some(depth);
+        }
+        hidden
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hidden_depth_covers_subtree() {
+        let mut hidden = HiddenDepth::default();
+        assert!(!hidden.visit(Path::new(".home"), 0, true));
+        assert!(hidden.visit(Path::new(".home/.config"), 1, true));
+        assert!(hidden.visit(Path::new(".home/.config/app/settings"), 3, false));
+        assert!(!hidden.visit(Path::new(".home/docs"), 1, true));
+        assert!(hidden.visit(Path::new(".home/docs/.draft"), 2, false));
+        assert!(!hidden.visit(Path::new(".home/docs/final"), 2, false));
+    }
+}
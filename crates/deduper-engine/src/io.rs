@@ -0,0 +1,657 @@
+//! File reading tuned for hashing large trees without trashing the page cache.
+//!
+//! A plain sequential read of a multi-terabyte tree pushes everyone else's
+//! cached data out of memory. On Linux the reader can instead tell the kernel
+//! to drop pages right after they are hashed (`posix_fadvise(DONTNEED)`) or
+//! bypass the cache entirely with `O_DIRECT`. Elsewhere the policy is ignored.
+//!
+//! Sparse files are read hole-aware on Linux: `SEEK_DATA`/`SEEK_HOLE` locate
+//! the holes, which are then served as zeros from memory instead of disk.
+//!
+//! For scans on busy servers, reads can share a [`Throttle`] capping the
+//! combined read rate of all workers, and [`set_priority`] lowers the CPU and
+//! I/O priority of the process.
+
+use anyhow::Result;
+use std::{
+    fs::File,
+    io::{BufRead, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Alignment O_DIRECT reads need for buffer address, offset and length.
+pub(crate) const DIRECT_ALIGN: usize = 4096;
+
+/// How often drop-behind gives consumed pages back to the kernel.
+const DROP_WINDOW: u64 = 8 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Let the kernel cache as usual.
+    #[default]
+    Normal,
+    /// Read with `POSIX_FADV_SEQUENTIAL` and drop pages once hashed.
+    DropBehind,
+    /// Bypass the page cache with `O_DIRECT`, falling back to drop-behind on
+    /// filesystems that refuse it.
+    Direct,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    /// Bytes requested per `read` call. Rounded up to 4 KiB for `Direct`.
+    pub buf_size: usize,
+    pub cache: CachePolicy,
+    /// Rate limit shared by every reader holding a clone of it.
+    pub throttle: Option<Arc<Throttle>>,
+    /// Files at least this large are memory-mapped and hashed on several
+    /// threads at once: one per algorithm, and BLAKE3 on as many as the pool
+    /// has. Only applies with `CachePolicy::Normal` and no throttle, since
+    /// neither can be enforced on a mapping.
+    pub parallel_above: Option<u64>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            buf_size: 8192,
+            cache: CachePolicy::Normal,
+            throttle: None,
+            parallel_above: None,
+        }
+    }
+}
+
+/// Token bucket limiting how many bytes per second all its users read
+/// together. Readers that overdraw it sleep until the debt is paid off.
+#[derive(Debug)]
+pub struct Throttle {
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            rate: bytes_per_sec.max(1) as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    /// Account for `n` bytes just read, sleeping as long as it takes to stay
+    /// within the rate. Idle time builds up at most a quarter second of credit.
+    pub fn consume(&self, n: u64) {
+        let wait = {
+            let mut b = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let refill = now.duration_since(b.last).as_secs_f64() * self.rate;
+            b.tokens = (b.tokens + refill).min(self.rate / 4.0) - n as f64;
+            b.last = now;
+            if b.tokens < 0.0 {
+                Duration::from_secs_f64(-b.tokens / self.rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+impl ReadOptions {
+    /// A zeroed buffer of the right size and, for `Direct`, alignment.
+    pub(crate) fn buffer(&self) -> Buffer {
+        let len = match self.cache {
+            CachePolicy::Direct => self.buf_size.max(1).div_ceil(DIRECT_ALIGN) * DIRECT_ALIGN,
+            _ => self.buf_size.max(1),
+        };
+        Buffer {
+            raw: vec![0; len + DIRECT_ALIGN],
+            len,
+        }
+    }
+}
+
+/// Heap buffer whose usable part starts on a `DIRECT_ALIGN` boundary.
+pub(crate) struct Buffer {
+    raw: Vec<u8>,
+    len: usize,
+}
+
+impl Buffer {
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+        let start = self.raw.as_ptr().align_offset(DIRECT_ALIGN);
+        &mut self.raw[start..start + self.len]
+    }
+}
+
+/// A file opened according to a [`CachePolicy`].
+pub struct Source {
+    file: File,
+    cache: CachePolicy,
+    len: u64,
+    pos: u64,
+    dropped_upto: u64,
+    eof: bool,
+    throttle: Option<Arc<Throttle>>,
+    /// Whether the file has fewer blocks allocated than its length implies.
+    sparse: bool,
+    /// While `pos` is below this, serve zeros without touching the disk.
+    hole_until: u64,
+    /// While `pos` is below this, read normally.
+    data_until: u64,
+    /// Bytes actually read from the file, as opposed to synthesised holes.
+    disk_bytes: u64,
+    nonzero_seen: bool,
+}
+
+/// Open `path` for one sequential pass.
+pub fn open(path: &Path, opts: &ReadOptions) -> Result<Source> {
+    let (file, cache) = open_with_policy(path, opts.cache)?;
+    #[cfg(target_os = "linux")]
+    if cache == CachePolicy::DropBehind {
+        advise(&file, 0, 0, libc::POSIX_FADV_SEQUENTIAL);
+    }
+    let md = file.metadata()?;
+    Ok(Source {
+        sparse: is_sparse(&md),
+        len: md.len(),
+        file,
+        cache,
+        pos: 0,
+        dropped_upto: 0,
+        eof: false,
+        throttle: opts.throttle.clone(),
+        hole_until: 0,
+        data_until: 0,
+        disk_bytes: 0,
+        nonzero_seen: false,
+    })
+}
+
+impl Source {
+    /// Length of the file when it was opened.
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// True if every byte returned so far was zero (vacuously so when empty).
+    pub fn all_zero(&self) -> bool {
+        !self.nonzero_seen
+    }
+
+    /// Continue reading at `offset`, which must be 4 KiB aligned for `Direct`.
+    pub(crate) fn seek_to(&mut self, offset: u64) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.pos = offset;
+        self.dropped_upto = offset;
+        self.hole_until = 0;
+        self.data_until = 0;
+        self.eof = false;
+        Ok(())
+    }
+
+    /// Find the hole or data run starting at `pos`.
+    #[cfg(target_os = "linux")]
+    fn next_segment(&mut self) -> std::io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        if self.pos >= self.len {
+            self.data_until = u64::MAX;
+            return Ok(());
+        }
+        let fd = self.file.as_raw_fd();
+        let pos = self.pos as libc::off_t;
+        // SAFETY: plain lseek calls on a descriptor we own.
+        let data = unsafe { libc::lseek(fd, pos, libc::SEEK_DATA) };
+        if data < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENXIO) {
+                // Nothing but hole from here to the end.
+                self.hole_until = self.len;
+            } else {
+                // The filesystem cannot tell; read everything.
+                self.sparse = false;
+                self.data_until = u64::MAX;
+            }
+        } else if data > pos {
+            self.hole_until = data as u64;
+        } else {
+            let hole = unsafe { libc::lseek(fd, pos, libc::SEEK_HOLE) };
+            self.data_until = if hole < 0 { u64::MAX } else { hole as u64 };
+        }
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn next_segment(&mut self) -> std::io::Result<()> {
+        self.sparse = false;
+        self.data_until = u64::MAX;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn is_sparse(md: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    md.blocks().saturating_mul(512) < md.len()
+}
+
+#[cfg(not(unix))]
+fn is_sparse(_md: &std::fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn open_with_policy(path: &Path, cache: CachePolicy) -> Result<(File, CachePolicy)> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if cache == CachePolicy::Direct {
+        match std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)
+        {
+            Ok(f) => return Ok((f, CachePolicy::Direct)),
+            // tmpfs and some FUSE filesystems reject O_DIRECT outright.
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                return Ok((File::open(path)?, CachePolicy::DropBehind))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok((File::open(path)?, cache))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_with_policy(path: &Path, _cache: CachePolicy) -> Result<(File, CachePolicy)> {
+    Ok((File::open(path)?, CachePolicy::Normal))
+}
+
+#[cfg(target_os = "linux")]
+fn advise(file: &File, offset: u64, len: u64, advice: libc::c_int) {
+    use std::os::unix::io::AsRawFd;
+
+    // Advice is only a hint; a failure changes nothing about correctness.
+    unsafe {
+        libc::posix_fadvise(
+            file.as_raw_fd(),
+            offset as libc::off_t,
+            len as libc::off_t,
+            advice,
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Priority {
+    #[default]
+    Normal,
+    /// Nice 10 and the lowest best-effort I/O priority.
+    Background,
+    /// Nice 19 and the idle I/O class: only read when nobody else does.
+    Idle,
+}
+
+/// Lower the CPU and I/O priority of the calling thread and every thread it
+/// starts afterwards, so call it before any worker pool exists. Linux only;
+/// a no-op elsewhere.
+pub fn set_priority(priority: Priority) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_BE: libc::c_int = 2;
+        const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+        let (nice, ioprio) = match priority {
+            Priority::Normal => return Ok(()),
+            Priority::Background => (10, IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT | 7),
+            Priority::Idle => (19, IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT),
+        };
+        // SAFETY: plain syscalls on the calling thread, no pointers involved.
+        unsafe {
+            if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = priority;
+    Ok(())
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.eof {
+            return Ok(0);
+        }
+        let mut want = buf.len();
+        if self.sparse {
+            if self.pos >= self.hole_until && self.pos >= self.data_until {
+                self.next_segment()?;
+            }
+            if self.pos < self.hole_until {
+                let n = want.min((self.hole_until - self.pos) as usize);
+                buf[..n].fill(0);
+                self.pos += n as u64;
+                if self.pos == self.hole_until {
+                    self.file.seek(SeekFrom::Start(self.pos))?;
+                }
+                return Ok(n);
+            }
+            want = want.min((self.data_until - self.pos).min(usize::MAX as u64) as usize);
+        }
+        // O_DIRECT only takes whole blocks, but a data run can end anywhere
+        // at the end of the file. Read the whole last block and keep the part
+        // asked for; the next segment lookup seeks back to `pos` anyway.
+        let keep = want;
+        if self.cache == CachePolicy::Direct {
+            want = want
+                .div_ceil(DIRECT_ALIGN)
+                .saturating_mul(DIRECT_ALIGN)
+                .min(buf.len());
+        }
+        let read = self.file.read(&mut buf[..want])?;
+        let n = read.min(keep);
+        self.pos += n as u64;
+        self.disk_bytes += n as u64;
+        if let Some(t) = &self.throttle {
+            t.consume(n as u64);
+        }
+        if !self.nonzero_seen {
+            self.nonzero_seen = buf[..n].iter().any(|&b| b != 0);
+        }
+        // With O_DIRECT the offset is unaligned after a short read, so any
+        // further read would fail rather than return 0.
+        if read == 0 || (self.cache == CachePolicy::Direct && read < want) {
+            self.eof = true;
+        }
+        #[cfg(target_os = "linux")]
+        if self.cache == CachePolicy::DropBehind && self.pos - self.dropped_upto >= DROP_WINDOW {
+            advise(
+                &self.file,
+                self.dropped_upto,
+                self.pos - self.dropped_upto,
+                libc::POSIX_FADV_DONTNEED,
+            );
+            self.dropped_upto = self.pos;
+        }
+        Ok(n)
+    }
+}
+
+/// A [`Source`] read through a buffer of its own, so that callers can read
+/// any amount and seek anywhere, even under `Direct`. Archive readers need
+/// this; hashing reads whole buffers and uses [`Source`] directly.
+pub(crate) struct Buffered {
+    source: Source,
+    buf: Buffer,
+    /// File offset of `buf`'s first byte.
+    start: u64,
+    /// Bytes of `buf` filled, and how many of those were consumed.
+    filled: usize,
+    consumed: usize,
+}
+
+impl Buffered {
+    pub(crate) fn new(source: Source, opts: &ReadOptions) -> Self {
+        // Archive readers ask for small pieces; a tiny `buf_size` would turn
+        // each into a syscall.
+        let opts = ReadOptions {
+            buf_size: opts.buf_size.max(64 * 1024),
+            ..opts.clone()
+        };
+        Self {
+            source,
+            buf: opts.buffer(),
+            start: 0,
+            filled: 0,
+            consumed: 0,
+        }
+    }
+}
+
+impl BufRead for Buffered {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.consumed == self.filled {
+            self.start += self.filled as u64;
+            self.filled = self.source.read(self.buf.as_mut_slice())?;
+            self.consumed = 0;
+        }
+        Ok(&self.buf.as_mut_slice()[self.consumed..self.filled])
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.consumed = (self.consumed + n).min(self.filled);
+    }
+}
+
+impl Read for Buffered {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        let avail = self.fill_buf()?;
+        let n = avail.len().min(out.len());
+        out[..n].copy_from_slice(&avail[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl Seek for Buffered {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let here = self.start + self.consumed as u64;
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(d) => self.source.len.checked_add_signed(d),
+            SeekFrom::Current(d) => here.checked_add_signed(d),
+        }
+        .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+        if target >= self.start && target <= self.start + self.filled as u64 {
+            self.consumed = (target - self.start) as usize;
+            return Ok(target);
+        }
+        let aligned = target - target % DIRECT_ALIGN as u64;
+        self.source.seek_to(aligned)?;
+        self.start = aligned;
+        self.filled = self.source.read(self.buf.as_mut_slice())?;
+        self.consumed = ((target - aligned) as usize).min(self.filled);
+        Ok(target)
+    }
+}
+
+impl Drop for Source {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if self.cache == CachePolicy::DropBehind {
+            advise(&self.file, self.dropped_upto, 0, libc::POSIX_FADV_DONTNEED);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn read_all(path: &Path, opts: &ReadOptions) -> Vec<u8> {
+        let mut src = open(path, opts).unwrap();
+        let mut buf = opts.buffer();
+        let buf = buf.as_mut_slice();
+        let mut out = Vec::new();
+        loop {
+            let n = src.read(buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        out
+    }
+
+    #[test]
+    fn test_every_policy_reads_the_same_bytes() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        temp_file.write_all(&data).unwrap();
+
+        for cache in [
+            CachePolicy::Normal,
+            CachePolicy::DropBehind,
+            CachePolicy::Direct,
+        ] {
+            let opts = ReadOptions {
+                buf_size: 5000,
+                cache,
+                ..Default::default()
+            };
+            assert_eq!(read_all(temp_file.path(), &opts), data, "{:?}", cache);
+        }
+    }
+
+    #[test]
+    fn test_sparse_file_reads_holes_as_zeros() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let mut f = temp_file.as_file();
+        f.set_len(64 * 1024 * 1024).unwrap();
+        f.seek(SeekFrom::Start(32 * 1024 * 1024)).unwrap();
+        f.write_all(b"data in the middle").unwrap();
+
+        let mut src = open(temp_file.path(), &ReadOptions::default()).unwrap();
+        let mut all = Vec::new();
+        src.read_to_end(&mut all).unwrap();
+
+        let mut expected = vec![0u8; 64 * 1024 * 1024];
+        expected[32 * 1024 * 1024..][..18].copy_from_slice(b"data in the middle");
+        assert!(all == expected);
+        assert!(!src.all_zero());
+        if cfg!(target_os = "linux") && src.sparse {
+            assert!(
+                src.disk_bytes < 1024 * 1024,
+                "read {} bytes",
+                src.disk_bytes
+            );
+        }
+    }
+
+    #[test]
+    fn test_sparse_file_reads_with_direct() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let mut f = temp_file.as_file();
+        let len = 8 * 1024 * 1024 + 100;
+        f.set_len(len).unwrap();
+        f.seek(SeekFrom::Start(4 * 1024 * 1024 + 7)).unwrap();
+        f.write_all(b"middle").unwrap();
+        f.seek(SeekFrom::Start(len - 3)).unwrap();
+        f.write_all(b"end").unwrap();
+
+        let mut expected = vec![0u8; len as usize];
+        expected[4 * 1024 * 1024 + 7..][..6].copy_from_slice(b"middle");
+        expected[len as usize - 3..].copy_from_slice(b"end");
+        let opts = ReadOptions {
+            buf_size: 64 * 1024,
+            cache: CachePolicy::Direct,
+            ..Default::default()
+        };
+        assert!(read_all(temp_file.path(), &opts) == expected);
+    }
+
+    #[test]
+    fn test_all_zero_detection() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(&[0u8; 10_000]).unwrap();
+        let mut src = open(temp_file.path(), &ReadOptions::default()).unwrap();
+        std::io::copy(&mut src, &mut std::io::sink()).unwrap();
+        assert!(src.all_zero());
+
+        temp_file.write_all(b"!").unwrap();
+        let mut src = open(temp_file.path(), &ReadOptions::default()).unwrap();
+        std::io::copy(&mut src, &mut std::io::sink()).unwrap();
+        assert!(!src.all_zero());
+    }
+
+    #[test]
+    fn test_throttle_caps_combined_rate() {
+        let throttle = Throttle::new(1_000_000);
+        let start = Instant::now();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..5 {
+                        throttle.consume(25_000);
+                    }
+                });
+            }
+        });
+        // 500 kB at 1 MB/s, starting from an empty bucket.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(450), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_throttled_reads_still_return_everything() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        let data = vec![5u8; 50_000];
+        temp_file.write_all(&data).unwrap();
+        let opts = ReadOptions {
+            throttle: Some(Arc::new(Throttle::new(10_000_000))),
+            ..Default::default()
+        };
+        assert_eq!(read_all(temp_file.path(), &opts), data);
+    }
+
+    #[test]
+    fn test_direct_buffer_is_aligned() {
+        let opts = ReadOptions {
+            buf_size: 5000,
+            cache: CachePolicy::Direct,
+            ..Default::default()
+        };
+        let mut buf = opts.buffer();
+        let slice = buf.as_mut_slice();
+        assert_eq!(slice.len(), 8192);
+        assert_eq!(slice.as_ptr() as usize % DIRECT_ALIGN, 0);
+    }
+
+    #[test]
+    fn test_buffered_reads_and_seeks_anywhere() {
+        let mut file = NamedTempFile::new().unwrap();
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        file.write_all(&data).unwrap();
+
+        for cache in [CachePolicy::Normal, CachePolicy::Direct] {
+            let opts = ReadOptions {
+                cache,
+                ..Default::default()
+            };
+            let mut r = Buffered::new(open(file.path(), &opts).unwrap(), &opts);
+            let mut got = [0u8; 10];
+            for at in [150_001u64, 3, 70_000, 199_990] {
+                r.seek(SeekFrom::Start(at)).unwrap();
+                r.read_exact(&mut got).unwrap();
+                assert_eq!(got, data[at as usize..at as usize + 10]);
+            }
+            r.seek(SeekFrom::End(-5)).unwrap();
+            let mut tail = Vec::new();
+            r.read_to_end(&mut tail).unwrap();
+            assert_eq!(tail, data[data.len() - 5..]);
+        }
+    }
+}
@@ -1,47 +1,590 @@
 //! Intelligent File Deduplicator Engine
 
+pub mod archives;
+pub mod cancel;
+pub mod chunking;
+pub mod db;
+pub mod devices;
+pub mod excludes;
 pub mod hashing;
+pub mod hidden;
 pub mod filtering;
+pub mod hardlinks;
+pub mod io;
+pub mod manifest;
+pub mod metadata;
+pub mod observer;
+pub mod order;
+pub mod outcome;
+pub mod pipeline;
 pub mod quarantine;
+pub mod report;
+pub mod resume;
+pub mod roots;
+pub mod stream;
+pub mod symlinks;
+pub mod verify;
 
 use serde::{Deserialize, Serialize};
-// Remove this unused import: use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct FileEntry {
     pub path: String,
-    pub hash: String,
+    pub hash: hashing::Digest,
+    /// Digests from `ScanOptions::extra_algos`, in the order requested.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra: Vec<hashing::Digest>,
+    /// The file holds nothing but zero bytes (or nothing at all). Such files
+    /// compare equal to every other zero file of the same size, but are
+    /// usually preallocated images or placeholders rather than real copies.
+    /// For digests taken from `ScanOptions::precomputed`, the file is read
+    /// only up to its first nonzero block to tell.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub all_zero: bool,
+    /// The path goes through a link followed under
+    /// [`SymlinkPolicy::Follow`](symlinks::SymlinkPolicy::Follow), so the
+    /// file is also reachable some other way. Never move or delete through it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub via_symlink: bool,
+    /// For a link listed under
+    /// [`SymlinkPolicy::Report`](symlinks::SymlinkPolicy::Report), where it
+    /// points. `hash` then digests that target path, not any file contents,
+    /// so links to the same place group together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlink: Option<String>,
+    /// Length in bytes when walked.
+    #[serde(default)]
+    pub size: u64,
+    /// The file behind the path, so hardlinks can be told apart from copies.
+    /// `None` for listed links and where the platform has no inodes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inode: Option<hardlinks::Inode>,
+    /// Role of the root the file was found under; see [`scan_roots`].
+    #[serde(default, skip_serializing_if = "roots::RootRole::is_candidate")]
+    pub role: roots::RootRole,
+    /// Times, mode and ownership as walked. Left at the default for files
+    /// in reports written before these were recorded.
+    #[serde(flatten)]
+    pub meta: metadata::FileMeta,
+    /// For a member of an archive opened under `ScanOptions::archives`, the
+    /// archive it was read from. `path` is then virtual, as in
+    /// `backup.zip!/docs/a.pdf`, and nothing can be moved or deleted there.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+}
+
+#[cfg(test)]
+impl FileEntry {
+    /// An entry for `path` with digest `hash` written as `algo:hex`, and
+    /// every other field empty.
+    pub(crate) fn test(path: &str, hash: &str) -> Self {
+        Self {
+            path: path.into(),
+            hash: hash.parse().unwrap(),
+            extra: Vec::new(),
+            all_zero: false,
+            via_symlink: false,
+            symlink: None,
+            size: 0,
+            inode: None,
+            role: Default::default(),
+            meta: Default::default(),
+            archive: None,
+        }
+    }
+}
+
+/// Knobs for [`scan_with`].
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Algorithm used for `FileEntry::hash` and thus for grouping.
+    pub algo: hashing::Algo,
+    /// Further digests computed in the same read, e.g. sha256 for manifests.
+    pub extra_algos: Vec<hashing::Algo>,
+    /// Known digests keyed by path relative to the scan root, typically from
+    /// an imported manifest. A file found here is not hashed, provided the
+    /// digest was made with `algo` and no `extra_algos` are requested. The
+    /// digest is trusted even if the file changed since, and a key matches
+    /// under every root, so callers should pair it with a single root.
+    pub precomputed: std::collections::HashMap<std::path::PathBuf, hashing::Digest>,
+    /// Read size and page-cache policy for full-file hashing.
+    pub io: io::ReadOptions,
+    /// Concurrent readers per device, keyed by any path on that device
+    /// (usually its mount point). Unlisted devices get
+    /// [`devices::default_threads`].
+    pub device_threads: Vec<(std::path::PathBuf, usize)>,
+    /// Order in which files are handed to the hashing workers.
+    pub order: order::ReadOrder,
+    /// Where to save progress on files larger than
+    /// [`resume::CHECKPOINT_EVERY`], so an interrupted scan can continue.
+    /// Used only when every requested algorithm is resumable, which xxh3
+    /// is not; see [`resume::is_resumable`].
+    pub checkpoints: Option<db::Index>,
+    /// Told about every directory, file, byte and failure as the scan goes.
+    pub observer: std::sync::Arc<dyn observer::ScanObserver>,
+    /// Trip this to stop the scan early; see [`cancel`].
+    pub cancel: cancel::CancelToken,
+    /// Stop the scan early once this moment has passed.
+    pub deadline: Option<std::time::Instant>,
+    /// Whether symbolic links are left out, followed or listed as such.
+    pub symlinks: symlinks::SymlinkPolicy,
+    /// Gitignore-style rules pruning the walk; see [`excludes`]. None by
+    /// default, not even from ignore files.
+    pub excludes: excludes::Excludes,
+    /// Walk no deeper than this many levels below each root; files directly
+    /// in a root are at depth 1.
+    pub max_depth: Option<usize>,
+    /// Leave out files less than this many levels below their root.
+    pub min_depth: usize,
+    /// Do not descend into directories on another device than their root,
+    /// such as mount points and network shares.
+    pub one_file_system: bool,
+    /// Whether hidden files are scanned, left out or the only ones scanned.
+    pub hidden: hidden::HiddenPolicy,
+    /// Also hash each file inside zip and tar archives as an entry of its
+    /// own; see [`archives`]. Used by [`scan_with`] and [`stream`], not by
+    /// [`pipeline`], which only looks at files on disk.
+    pub archives: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            algo: hashing::Algo::Sha256,
+            extra_algos: Vec::new(),
+            precomputed: Default::default(),
+            io: Default::default(),
+            device_threads: Vec::new(),
+            order: order::ReadOrder::Walk,
+            checkpoints: None,
+            observer: std::sync::Arc::new(observer::NoopObserver),
+            cancel: Default::default(),
+            deadline: None,
+            symlinks: symlinks::SymlinkPolicy::Skip,
+            excludes: Default::default(),
+            max_depth: None,
+            min_depth: 0,
+            one_file_system: false,
+            hidden: hidden::HiddenPolicy::Include,
+            archives: false,
+        }
+    }
 }
 
 /// Recursively scan directory and hash matching files
+///
+/// Paths that cannot be read are left out; [`scan_with`] reports them.
 pub fn scan_directory(
     root: &std::path::Path,
     filter: &filtering::Filter,
     algo: hashing::Algo,
 ) -> anyhow::Result<Vec<FileEntry>> {
-    use rayon::prelude::*;
+    let opts = ScanOptions {
+        algo,
+        ..Default::default()
+    };
+    Ok(scan_with(root, filter, &opts)?.entries)
+}
+
+/// Like [`scan_directory`], with every knob in [`ScanOptions`] available and
+/// the paths that failed returned alongside the entries.
+///
+/// A cancelled or timed-out scan still returns what it found so far, with
+/// `ScanOutcome::stopped` set.
+pub fn scan_with(
+    root: &std::path::Path,
+    filter: &filtering::Filter,
+    opts: &ScanOptions,
+) -> anyhow::Result<outcome::ScanOutcome> {
+    scan_roots(&[roots::ScanRoot::candidate(root)], filter, opts)
+}
+
+/// [`scan_with`] over several roots, each entry labelled with the role of
+/// the root it was found under.
+pub fn scan_roots(
+    roots: &[roots::ScanRoot],
+    filter: &filtering::Filter,
+    opts: &ScanOptions,
+) -> anyhow::Result<outcome::ScanOutcome> {
+    let stop = opts.stop();
+    let (mut files, mut errors) = matching_files(roots, filter, opts, &stop);
+    if opts.order == order::ReadOrder::Disk {
+        order::sort_by_disk(&mut files, |c| c);
+    }
+    let pools = devices::DevicePools::new(&opts.device_threads);
+
+    let hashed = pools.par_filter_map(
+        &files,
+        |c| c.dev,
+        |c| hash_candidate(&roots[c.root], c, opts, &stop),
+    );
+    let members = archives::member_entries(&files, roots, filter, opts, &pools, &stop);
+    let mut entries = Vec::with_capacity(hashed.len() + members.len());
+    for r in hashed.into_iter().chain(members) {
+        match r {
+            Ok(e) => entries.push(e),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    Ok(outcome::ScanOutcome {
+        entries,
+        errors,
+        stopped: stop.reason(),
+    })
+}
+
+impl ScanOptions {
+    /// A fresh stop check for one scan, from `cancel` and `deadline`.
+    pub(crate) fn stop(&self) -> cancel::Stop {
+        cancel::Stop::new(&self.cancel, self.deadline)
+    }
+}
+
+/// [`hash_entry`] for a file walked under `root`, with failures attributed
+/// to its path. `None` once `stop` fires: the file is left out, not counted
+/// as failed.
+pub(crate) fn hash_candidate(
+    root: &roots::ScanRoot,
+    c: &Candidate,
+    opts: &ScanOptions,
+    stop: &cancel::Stop,
+) -> Option<Result<FileEntry, outcome::ScanError>> {
+    if c.members_only || stop.check() {
+        return None;
+    }
+    let hashed = match &c.symlink {
+        Some(target) => link_entry(c, target, opts),
+        None => hash_entry(&root.path, &c.path, opts, stop),
+    };
+    Some(match hashed {
+        Ok(mut entry) => {
+            entry.via_symlink = c.via_symlink;
+            entry.size = c.size;
+            entry.inode = c.inode();
+            entry.role = root.role;
+            entry.meta = c.meta.clone();
+            opts.observer.file_hashed(&entry);
+            Ok(entry)
+        }
+        Err(e) if cancel::is_interrupted(&e) => return None,
+        Err(e) => {
+            let err = outcome::ScanError::from_anyhow(c.path.clone(), &e);
+            opts.observer.error(&err);
+            Err(err)
+        }
+    })
+}
+
+/// Hash one file as `opts` asks, or reuse its precomputed digest.
+pub(crate) fn hash_entry(
+    root: &std::path::Path,
+    path: &std::path::Path,
+    opts: &ScanOptions,
+    stop: &cancel::Stop,
+) -> anyhow::Result<FileEntry> {
+    let display = path.to_string_lossy().into_owned();
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    if opts.extra_algos.is_empty() {
+        if let Some(&hash) = opts.precomputed.get(rel).filter(|d| d.algo() == opts.algo) {
+            return Ok(FileEntry {
+                path: display,
+                hash,
+                extra: Vec::new(),
+                all_zero: hashing::is_all_zero(path, &opts.io)?,
+                via_symlink: false,
+                symlink: None,
+                size: 0,
+                inode: None,
+                role: Default::default(),
+                meta: Default::default(),
+                archive: None,
+            });
+        }
+    }
+
+    let mut algos = vec![opts.algo];
+    algos.extend_from_slice(&opts.extra_algos);
+    let mut hashed = match &opts.checkpoints {
+        Some(index)
+            if algos.iter().all(|&a| resume::is_resumable(a))
+                && std::fs::metadata(path)?.len() > resume::CHECKPOINT_EVERY =>
+        {
+            resume::hash_file_observed(path, &algos, &opts.io, index, &*opts.observer, stop)?
+        }
+        _ => hashing::hash_file_observed(path, &algos, &opts.io, &*opts.observer, stop)?,
+    };
+    Ok(FileEntry {
+        path: display,
+        hash: hashed.digests.remove(0),
+        extra: hashed.digests,
+        all_zero: hashed.all_zero,
+        via_symlink: false,
+        symlink: None,
+        size: 0,
+        inode: None,
+        role: Default::default(),
+        meta: Default::default(),
+        archive: None,
+    })
+}
+
+/// The entry for a link under `SymlinkPolicy::Report`, hashed over `target`.
+fn link_entry(
+    c: &Candidate,
+    target: &std::path::Path,
+    opts: &ScanOptions,
+) -> anyhow::Result<FileEntry> {
+    let bytes = target.as_os_str().as_encoded_bytes();
+    let mut digests = std::iter::once(&opts.algo)
+        .chain(&opts.extra_algos)
+        .map(|&a| hashing::hash_bytes(bytes, a))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(FileEntry {
+        path: c.path.to_string_lossy().into_owned(),
+        hash: digests.remove(0),
+        extra: digests,
+        all_zero: false,
+        via_symlink: false,
+        symlink: Some(target.to_string_lossy().into_owned()),
+        size: 0,
+        inode: None,
+        role: Default::default(),
+        meta: Default::default(),
+        archive: None,
+    })
+}
+
+/// Group entries sharing a hash, keeping only groups that hold two or more
+/// physical copies: hardlinks to one inode alone are not duplicates.
+///
+/// Groups and their members keep the order in which they first appear.
+pub fn duplicate_groups(entries: &[FileEntry]) -> Vec<Vec<&FileEntry>> {
+    let mut index: std::collections::HashMap<hashing::Digest, usize> =
+        std::collections::HashMap::new();
+    let mut groups: Vec<Vec<&FileEntry>> = Vec::new();
+    for e in entries {
+        let i = *index.entry(e.hash).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[i].push(e);
+    }
+    groups.retain(|g| hardlinks::physical_copies(g).len() > 1);
+    groups
+}
+
+/// A file found by the walk, with the metadata later stages need.
+#[derive(Debug, Clone)]
+pub(crate) struct Candidate {
+    pub path: std::path::PathBuf,
+    /// Index of the scan root it was found under.
+    pub root: usize,
+    pub size: u64,
+    pub dev: devices::DeviceId,
+    /// Inode number, or 0 where the platform has none.
+    pub ino: u64,
+    /// Paths to the inode, or 1 where the platform does not say.
+    pub nlink: u64,
+    /// Reached through a followed link; see `FileEntry::via_symlink`.
+    pub via_symlink: bool,
+    /// Target of a link listed under `SymlinkPolicy::Report`.
+    pub symlink: Option<std::path::PathBuf>,
+    pub meta: metadata::FileMeta,
+    /// An archive the filter turned down, walked only so that its members
+    /// can be scanned under `ScanOptions::archives`. Not hashed itself.
+    pub members_only: bool,
+}
+
+impl Candidate {
+    pub(crate) fn new(path: std::path::PathBuf, md: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let ino = std::os::unix::fs::MetadataExt::ino(md);
+        #[cfg(unix)]
+        let nlink = std::os::unix::fs::MetadataExt::nlink(md);
+        #[cfg(not(unix))]
+        let (ino, nlink) = (0, 1);
+        Self {
+            size: md.len(),
+            dev: devices::device_of(md),
+            ino,
+            nlink,
+            path,
+            root: 0,
+            via_symlink: false,
+            symlink: None,
+            meta: metadata::FileMeta::from_metadata(md),
+            members_only: false,
+        }
+    }
+
+    /// The inode this file is, unless it is a listed link or unknown.
+    fn inode(&self) -> Option<hardlinks::Inode> {
+        (self.ino != 0 && self.symlink.is_none()).then_some(hardlinks::Inode {
+            dev: self.dev,
+            ino: self.ino,
+            nlink: self.nlink,
+        })
+    }
+}
+
+/// The files a scan of `roots` would hash, without reading any of them, and
+/// the paths that could not be walked.
+pub fn list_files(
+    roots: &[roots::ScanRoot],
+    filter: &filtering::Filter,
+    opts: &ScanOptions,
+) -> (Vec<std::path::PathBuf>, Vec<outcome::ScanError>) {
+    let (files, errors) = matching_files(roots, filter, opts, &opts.stop());
+    let files = files.into_iter().filter(|c| !c.members_only).map(|c| c.path);
+    (files.collect(), errors)
+}
+
+/// Walk `roots` and return every regular file accepted by `filter`, plus
+/// whatever could not be walked.
+pub(crate) fn matching_files(
+    roots: &[roots::ScanRoot],
+    filter: &filtering::Filter,
+    opts: &ScanOptions,
+    stop: &cancel::Stop,
+) -> (Vec<Candidate>, Vec<outcome::ScanError>) {
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    for r in walk_candidates(roots, filter, opts, stop) {
+        match r {
+            Ok(c) => files.push(c),
+            Err(e) => errors.push(e),
+        }
+    }
+    (files, errors)
+}
+
+/// Lazily walk `roots` one after another, yielding regular files accepted
+/// by `filter` and telling `opts.observer` what was entered, matched, skipped
+/// or failed. Links are handled per `opts.symlinks`. The walk ends early
+/// once `stop` fires.
+pub(crate) fn walk_candidates<'a>(
+    roots: &'a [roots::ScanRoot],
+    filter: &'a filtering::Filter,
+    opts: &'a ScanOptions,
+    stop: &'a cancel::Stop,
+) -> impl Iterator<Item = Result<Candidate, outcome::ScanError>> + 'a {
+    (0..roots.len()).flat_map(move |i| walk_root(roots, i, filter, opts, stop))
+}
+
+/// [`walk_candidates`] for `roots[index]`, leaving out whatever another
+/// root covers; see [`roots::owner`].
+fn walk_root<'a>(
+    roots: &'a [roots::ScanRoot],
+    index: usize,
+    filter: &'a filtering::Filter,
+    opts: &'a ScanOptions,
+    stop: &'a cancel::Stop,
+) -> impl Iterator<Item = Result<Candidate, outcome::ScanError>> + 'a {
+    use hidden::HiddenPolicy;
+    use symlinks::SymlinkPolicy;
     use walkdir::WalkDir;
 
-    let files: Vec<_> = WalkDir::new(root)
+    let observer = &*opts.observer;
+    let mut links = symlinks::LinkDepth::default();
+    let mut hidden = hidden::HiddenDepth::default();
+    let failed = move |err: &walkdir::Error| {
+        let err = outcome::ScanError::from_walk(err);
+        observer.error(&err);
+        Some(Err(err))
+    };
+    let elsewhere = move |path: &std::path::Path| roots::owner(roots, path) != Some(index);
+    let mut pruner = excludes::Pruner::new(&opts.excludes, &roots[index].path);
+    WalkDir::new(&roots[index].path)
+        .follow_links(opts.symlinks == SymlinkPolicy::Follow)
+        .max_depth(opts.max_depth.unwrap_or(usize::MAX))
+        .same_file_system(opts.one_file_system)
         .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| e.file_type().is_file())
-        .collect();
-
-    let entries: Vec<_> = files
-        .par_iter()
-        .filter(|e| filter.matches(&e.metadata().unwrap(), e.path()))
-        .filter_map(|e| {
-            hashing::hash_file(e.path(), algo)
-                .ok()
-                .map(|digest| FileEntry {
-                    path: e.path().to_string_lossy().into_owned(),
-                    hash: digest,
-                })
+        // Our own index and quarantine, never scan material.
+        .filter_entry(move |e| {
+            if (e.depth() > 0 && e.file_name() == ".deduper") || elsewhere(e.path()) {
+                return false;
+            }
+            if pruner.excluded(e.path(), e.depth(), e.file_type().is_dir()) {
+                observer.file_skipped(e.path(), filtering::SkipReason::Excluded);
+                return false;
+            }
+            if opts.hidden == HiddenPolicy::Exclude
+                && e.depth() > 0
+                && deduper_utils::file_utils::is_hidden(e.path())
+            {
+                observer.file_skipped(e.path(), filtering::SkipReason::Hidden);
+                return false;
+            }
+            true
+        })
+        .take_while(move |_| !stop.check())
+        .filter_map(move |r| {
+            let e = match r {
+                Ok(e) => e,
+                Err(err) => return failed(&err),
+            };
+            let is_dir = e.file_type().is_dir();
+            let via_symlink = links.visit(e.depth(), e.path_is_symlink(), is_dir);
+            // Checked only when needed, as it may cost a syscall per entry.
+            let only_hidden = opts.hidden == HiddenPolicy::Only;
+            let is_hidden = only_hidden && hidden.visit(e.path(), e.depth(), is_dir);
+            if is_dir {
+                observer.dir_entered(e.path());
+                return None;
+            }
+            // Only seen when links are not followed.
+            let is_link = e.file_type().is_symlink();
+            // Checked here rather than by WalkDir, which would hide the
+            // directories above from `links` and the observer.
+            if e.depth() < opts.min_depth {
+                observer.file_skipped(e.path(), filtering::SkipReason::TooShallow);
+                return None;
+            }
+            if only_hidden && !is_hidden {
+                observer.file_skipped(e.path(), filtering::SkipReason::NotHidden);
+                return None;
+            }
+            if is_link && opts.symlinks == SymlinkPolicy::Skip {
+                observer.file_skipped(e.path(), filtering::SkipReason::Symlink);
+                return None;
+            }
+            if !is_link && !e.file_type().is_file() {
+                observer.file_skipped(e.path(), filtering::SkipReason::NotAFile);
+                return None;
+            }
+            let md = match e.metadata() {
+                Ok(md) => md,
+                Err(err) => return failed(&err),
+            };
+            let mut members_only = false;
+            if let Err(reason) = filter.check(&md, e.path()) {
+                observer.file_skipped(e.path(), reason);
+                // Its members are filtered on their own.
+                if !opts.archives || is_link || archives::ArchiveKind::of(e.path()).is_none() {
+                    return None;
+                }
+                members_only = true;
+            }
+            let symlink = match is_link.then(|| std::fs::read_link(e.path())).transpose() {
+                Ok(target) => target,
+                Err(err) => {
+                    let err = outcome::ScanError::from_io(e.path().to_path_buf(), &err);
+                    observer.error(&err);
+                    return Some(Err(err));
+                }
+            };
+            if !members_only {
+                observer.file_matched(e.path(), md.len());
+            }
+            Some(Ok(Candidate {
+                root: index,
+                via_symlink,
+                symlink,
+                members_only,
+                ..Candidate::new(e.into_path(), &md)
+            }))
         })
-        .collect();
-
-    Ok(entries)
 }
 
 #[cfg(test)]
@@ -90,4 +633,407 @@ mod tests {
         assert_eq!(result.len(), 2);
         assert_eq!(result[0].hash, result[1].hash);
     }
+
+    #[test]
+    fn test_scan_with_extra_digests() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("a.txt");
+        std::fs::write(&path, "Hello World\n").unwrap();
+
+        let filter = filtering::Filter::any();
+        let opts = ScanOptions {
+            algo: hashing::Algo::Xxh3,
+            extra_algos: vec![hashing::Algo::Sha256, hashing::Algo::Blake3],
+            ..Default::default()
+        };
+
+        let result = scan_with(temp_dir.path(), &filter, &opts).unwrap().entries;
+        assert_eq!(result[0].hash.algo(), hashing::Algo::Xxh3);
+        assert_eq!(result[0].extra.len(), 2);
+        assert_eq!(result[0].extra[0], hashing::hash_file(&path, hashing::Algo::Sha256).unwrap());
+        assert_eq!(result[0].extra[1].algo(), hashing::Algo::Blake3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_reports_unreadable_paths() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("ok.txt"), "fine").unwrap();
+        let locked = temp_dir.path().join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        std::fs::write(locked.join("hidden.txt"), "secret").unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        // Root ignores permission bits; nothing to observe then.
+        let readable = std::fs::read_dir(&locked).is_ok();
+
+        let filter = filtering::Filter::any();
+        let out = scan_with(temp_dir.path(), &filter, &ScanOptions::default()).unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        if !readable {
+            assert_eq!(out.entries.len(), 1);
+            assert_eq!(out.errors.len(), 1);
+            assert_eq!(out.errors[0].kind, outcome::ScanErrorKind::PermissionDenied);
+            assert_eq!(out.errors[0].path, locked);
+        }
+    }
+
+    #[test]
+    fn test_hash_failure_is_reported_not_dropped() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("gone.txt");
+        std::fs::write(&path, "soon deleted").unwrap();
+        let md = std::fs::metadata(&path).unwrap();
+        let c = Candidate::new(path.clone(), &md);
+        std::fs::remove_file(&path).unwrap();
+
+        let opts = ScanOptions::default();
+        let root = roots::ScanRoot::candidate(temp_dir.path());
+        let err = hash_candidate(&root, &c, &opts, &opts.stop())
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.kind, outcome::ScanErrorKind::Vanished);
+        assert_eq!(err.path, path);
+    }
+
+    #[test]
+    fn test_stopped_scan_is_marked_incomplete() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "content").unwrap();
+        let filter = filtering::Filter::any();
+
+        let later = std::time::Instant::now() + std::time::Duration::from_secs(3600);
+        let opts = ScanOptions {
+            deadline: Some(later),
+            ..Default::default()
+        };
+        let scan = scan_with(temp_dir.path(), &filter, &opts).unwrap();
+        assert_eq!((scan.entries.len(), scan.stopped), (1, None));
+
+        opts.cancel.cancel();
+        let scan = scan_with(temp_dir.path(), &filter, &opts).unwrap();
+        assert!(scan.entries.is_empty() && scan.errors.is_empty());
+        assert_eq!(scan.stopped, Some(cancel::StopReason::Cancelled));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_policies() {
+        use std::os::unix::fs::symlink;
+        use symlinks::SymlinkPolicy;
+
+        let temp_dir = TempDir::new().unwrap();
+        let real = temp_dir.path().join("real");
+        std::fs::create_dir(&real).unwrap();
+        std::fs::write(real.join("a.txt"), "content").unwrap();
+        symlink(&real, temp_dir.path().join("alias")).unwrap();
+        symlink(real.join("a.txt"), temp_dir.path().join("b.txt")).unwrap();
+        symlink("..", real.join("up")).unwrap();
+        let filter = filtering::Filter::any();
+        let scan = |symlinks| {
+            let opts = ScanOptions {
+                symlinks,
+                ..Default::default()
+            };
+            let mut scan = scan_with(temp_dir.path(), &filter, &opts).unwrap();
+            scan.entries.sort_by(|a, b| a.path.cmp(&b.path));
+            scan
+        };
+        let name = |e: &FileEntry| {
+            let rel = std::path::Path::new(&e.path).strip_prefix(temp_dir.path());
+            rel.unwrap().to_string_lossy().into_owned()
+        };
+
+        let skipped = scan(SymlinkPolicy::Skip);
+        assert_eq!(skipped.entries.iter().map(name).collect::<Vec<_>>(), ["real/a.txt"]);
+        assert!(skipped.errors.is_empty());
+
+        let followed = scan(SymlinkPolicy::Follow);
+        let found: Vec<_> = followed
+            .entries
+            .iter()
+            .map(|e| (name(e), e.via_symlink))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("alias/a.txt".to_string(), true),
+                ("b.txt".to_string(), true),
+                ("real/a.txt".to_string(), false),
+            ]
+        );
+        assert!(followed.entries.iter().all(|e| e.hash == followed.entries[0].hash));
+        assert!(!followed.errors.is_empty());
+        assert!(followed
+            .errors
+            .iter()
+            .all(|e| e.kind == outcome::ScanErrorKind::SymlinkLoop));
+
+        let reported = scan(SymlinkPolicy::Report);
+        let links: Vec<_> = reported
+            .entries
+            .iter()
+            .filter_map(|e| Some((name(e), e.symlink.clone()?)))
+            .collect();
+        assert_eq!(links.len(), 3);
+        assert!(links.contains(&("real/up".to_string(), "..".to_string())));
+        assert_eq!(reported.entries.len(), 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hardlinks_are_one_copy() {
+        let temp_dir = TempDir::new().unwrap();
+        let a = temp_dir.path().join("a.txt");
+        std::fs::write(&a, "same bytes").unwrap();
+        std::fs::hard_link(&a, temp_dir.path().join("b.txt")).unwrap();
+        let filter = filtering::Filter::any();
+
+        let entries = scan_directory(temp_dir.path(), &filter, hashing::Algo::Sha256).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].inode, entries[1].inode);
+        assert_eq!(entries[0].inode.unwrap().nlink, 2);
+        assert!(duplicate_groups(&entries).is_empty());
+        assert_eq!(hardlinks::hardlink_sets(&entries).len(), 1);
+
+        std::fs::write(temp_dir.path().join("c.txt"), "same bytes").unwrap();
+        let entries = scan_directory(temp_dir.path(), &filter, hashing::Algo::Sha256).unwrap();
+        let groups = duplicate_groups(&entries);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 3);
+        let mut copies: Vec<usize> = hardlinks::physical_copies(&groups[0])
+            .iter()
+            .map(Vec::len)
+            .collect();
+        copies.sort();
+        assert_eq!(copies, [1, 2]);
+        // Whichever copy is kept, the other goes entirely: c.txt alone, or
+        // both links to a.txt's inode.
+        assert_eq!(hardlinks::reclaimable_bytes(&groups), 10);
+    }
+
+    #[test]
+    fn test_entries_carry_metadata_and_old_reports_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("a.txt");
+        std::fs::write(&path, "dated").unwrap();
+        let filter = filtering::Filter::any();
+
+        let entries = scan_directory(temp_dir.path(), &filter, hashing::Algo::Xxh3).unwrap();
+        let md = std::fs::metadata(&path).unwrap();
+        assert_eq!(entries[0].size, 5);
+        assert_eq!(entries[0].meta, metadata::FileMeta::from_metadata(&md));
+        let json = serde_json::to_string(&entries[0]).unwrap();
+        assert!(json.contains("\"mtime\":"));
+        assert_eq!(serde_json::from_str::<FileEntry>(&json).unwrap(), entries[0]);
+
+        let old = r#"{"path": "a.txt", "hash": "00000000000000ff"}"#;
+        let entry: FileEntry = serde_json::from_str(old).unwrap();
+        assert_eq!(entry.size, 0);
+        assert_eq!(entry.meta, metadata::FileMeta::default());
+    }
+
+    #[test]
+    fn test_archive_members_group_but_are_never_removed() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::write(root.join("a.txt"), "extracted").unwrap();
+        let mut zip = zip::ZipWriter::new(File::create(root.join("backup.zip")).unwrap());
+        zip.start_file("a.txt", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"extracted").unwrap();
+        zip.finish().unwrap();
+        let filter = filtering::Filter {
+            ext: Some("txt".into()),
+            ..filtering::Filter::any()
+        };
+
+        let scan = scan_with(root, &filter, &ScanOptions::default()).unwrap();
+        assert_eq!(scan.entries.len(), 1);
+
+        let opts = ScanOptions {
+            archives: true,
+            ..Default::default()
+        };
+        let scan = scan_with(root, &filter, &opts).unwrap();
+        assert!(scan.errors.is_empty());
+        let groups = duplicate_groups(&scan.entries);
+        assert_eq!(groups.len(), 1);
+        assert!(groups[0].iter().any(|e| e.path.ends_with("backup.zip!/a.txt")));
+        assert!(roots::keeper(&groups[0]).archive.is_none());
+        assert!(roots::removable_copies(&groups[0]).is_empty());
+    }
+
+    #[test]
+    fn test_excluded_directories_are_not_walked() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("target/debug")).unwrap();
+        std::fs::create_dir(root.join("src")).unwrap();
+        std::fs::write(root.join("target/debug/out.bin"), "built").unwrap();
+        std::fs::write(root.join("src/main.rs"), "code").unwrap();
+        std::fs::write(root.join("src/notes.tmp"), "scratch").unwrap();
+        std::fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        let filter = filtering::Filter::any();
+        let roots = [roots::ScanRoot::candidate(root)];
+        let opts = ScanOptions {
+            excludes: excludes::Excludes::new(&["*.tmp"], true).unwrap(),
+            ..Default::default()
+        };
+
+        let (files, errors) = list_files(&roots, &filter, &opts);
+        assert!(errors.is_empty());
+        let mut names: Vec<_> = files.iter().map(|p| p.strip_prefix(root).unwrap()).collect();
+        names.sort();
+        assert_eq!(names, [std::path::Path::new(".gitignore"), "src/main.rs".as_ref()]);
+
+        let opts = ScanOptions::default();
+        assert_eq!(list_files(&roots, &filter, &opts).0.len(), 4);
+    }
+
+    #[test]
+    fn test_depth_limits() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        for f in ["top.txt", "a/mid.txt", "a/b/deep.txt"] {
+            std::fs::write(root.join(f), f).unwrap();
+        }
+        let filter = filtering::Filter::any();
+        let roots = [roots::ScanRoot::candidate(root)];
+        let names = |opts: ScanOptions| {
+            let mut names: Vec<_> = list_files(&roots, &filter, &opts)
+                .0
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+
+        let shallow = ScanOptions {
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(names(shallow), ["mid.txt", "top.txt"]);
+        let deep = ScanOptions {
+            min_depth: 2,
+            ..Default::default()
+        };
+        assert_eq!(names(deep), ["deep.txt", "mid.txt"]);
+        let same_device = ScanOptions {
+            one_file_system: true,
+            ..Default::default()
+        };
+        assert_eq!(names(same_device).len(), 3);
+    }
+
+    #[test]
+    fn test_hidden_policies() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join(".git/objects")).unwrap();
+        std::fs::write(root.join(".git/objects/pack"), "pack").unwrap();
+        std::fs::write(root.join(".env"), "env").unwrap();
+        std::fs::write(root.join("readme"), "readme").unwrap();
+        let filter = filtering::Filter::any();
+        let roots = [roots::ScanRoot::candidate(root)];
+        let names = |policy| {
+            let opts = ScanOptions {
+                hidden: policy,
+                ..Default::default()
+            };
+            let mut names: Vec<_> = list_files(&roots, &filter, &opts)
+                .0
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+
+        use hidden::HiddenPolicy;
+        assert_eq!(names(HiddenPolicy::Include), [".env", "pack", "readme"]);
+        assert_eq!(names(HiddenPolicy::Exclude), ["readme"]);
+        assert_eq!(names(HiddenPolicy::Only), [".env", "pack"]);
+    }
+
+    #[test]
+    fn test_scan_roots_labels_nested_root_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let archive = temp_dir.path().join("archive");
+        std::fs::create_dir(&archive).unwrap();
+        std::fs::write(temp_dir.path().join("new.txt"), "same").unwrap();
+        std::fs::write(archive.join("old.txt"), "same").unwrap();
+        let filter = filtering::Filter::any();
+
+        let roots = [
+            roots::ScanRoot::candidate(temp_dir.path()),
+            roots::ScanRoot::reference(&archive),
+        ];
+        let scan = scan_roots(&roots, &filter, &ScanOptions::default()).unwrap();
+        let mut found: Vec<_> = scan
+            .entries
+            .iter()
+            .map(|e| (std::path::Path::new(&e.path).file_name().unwrap().to_owned(), e.role))
+            .collect();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            found,
+            [
+                ("new.txt".into(), roots::RootRole::Candidate),
+                ("old.txt".into(), roots::RootRole::Reference),
+            ]
+        );
+        let groups = duplicate_groups(&scan.entries);
+        assert!(roots::keeper(&groups[0]).path.ends_with("old.txt"));
+    }
+
+    #[test]
+    fn test_candidate_root_inside_reference_root_is_reference() {
+        let temp_dir = TempDir::new().unwrap();
+        let archive = temp_dir.path().join("archive");
+        let inbox = archive.join("inbox");
+        std::fs::create_dir_all(&inbox).unwrap();
+        std::fs::write(archive.join("old.txt"), "same").unwrap();
+        std::fs::write(inbox.join("new.txt"), "same").unwrap();
+        let filter = filtering::Filter::any();
+
+        let roots = [
+            roots::ScanRoot::candidate(&inbox),
+            roots::ScanRoot::reference(&archive),
+        ];
+        let scan = scan_roots(&roots, &filter, &ScanOptions::default()).unwrap();
+        assert_eq!(scan.entries.len(), 2);
+        assert!(scan
+            .entries
+            .iter()
+            .all(|e| e.role == roots::RootRole::Reference));
+        let groups = duplicate_groups(&scan.entries);
+        assert!(roots::removable_copies(&groups[0]).is_empty());
+    }
+
+    #[test]
+    fn test_scan_uses_precomputed_digest() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "real content").unwrap();
+
+        let filter = filtering::Filter::any();
+        let known: hashing::Digest = "xxh3:0123456789abcdef".parse().unwrap();
+        let mut opts = ScanOptions {
+            algo: hashing::Algo::Xxh3,
+            ..Default::default()
+        };
+        opts.precomputed.insert("a.txt".into(), known);
+
+        let result = scan_with(temp_dir.path(), &filter, &opts).unwrap().entries;
+        assert_eq!(result[0].hash, known);
+        assert!(!result[0].all_zero);
+
+        std::fs::write(temp_dir.path().join("a.txt"), [0u8; 64]).unwrap();
+        let result = scan_with(temp_dir.path(), &filter, &opts).unwrap().entries;
+        assert_eq!(result[0].hash, known);
+        assert!(result[0].all_zero);
+    }
 }
@@ -0,0 +1,237 @@
+//! `sha256sum` / `b3sum` compatible checksum manifests.
+//!
+//! Lines look like `<hex>  <path>` (text mode) or `<hex> *<path>` (binary
+//! mode). Paths containing a backslash or newline are escaped the way GNU
+//! coreutils does it: the line starts with `\` and those characters are
+//! written as `\\` and `\n`.
+
+use crate::{
+    hashing::{self, Algo, Digest},
+    FileEntry,
+};
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub digest: Digest,
+    /// Path exactly as written in the manifest, usually relative.
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Status {
+    Ok,
+    Failed,
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckResult {
+    pub path: String,
+    pub status: Status,
+}
+
+/// Parse manifest text whose digests were made with `algo`.
+///
+/// Blank lines are skipped; any other malformed line is an error naming its
+/// line number.
+pub fn parse(text: &str, algo: Algo) -> Result<Vec<ManifestEntry>> {
+    let mut out = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = parse_line(line, algo).map_err(|e| anyhow!("line {}: {}", n + 1, e))?;
+        out.push(entry);
+    }
+    Ok(out)
+}
+
+fn parse_line(line: &str, algo: Algo) -> Result<ManifestEntry> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (hex, rest) = line
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("expected `<digest>  <path>`"))?;
+    let path = rest
+        .strip_prefix(' ')
+        .or_else(|| rest.strip_prefix('*'))
+        .ok_or_else(|| anyhow!("expected two spaces or ` *` after the digest"))?;
+    if path.is_empty() {
+        bail!("missing path");
+    }
+    Ok(ManifestEntry {
+        digest: Digest::from_hex(algo, hex)?,
+        path: if escaped {
+            unescape(path)?
+        } else {
+            path.to_string()
+        },
+    })
+}
+
+/// Render one manifest line per entry, with paths relative to `root`.
+///
+/// The digest is taken from `FileEntry::hash` or `FileEntry::extra`,
+/// whichever was made with `algo`; entries lacking one are an error.
+pub fn render(entries: &[FileEntry], root: &Path, algo: Algo) -> Result<String> {
+    let mut out = String::new();
+    for e in entries {
+        let digest = std::iter::once(&e.hash)
+            .chain(&e.extra)
+            .find(|d| d.algo() == algo)
+            .ok_or_else(|| anyhow!("{} has no {} digest", e.path, algo))?;
+        let path = Path::new(&e.path);
+        let rel = path.strip_prefix(root).unwrap_or(path).to_string_lossy();
+        if rel.contains(['\\', '\n']) {
+            out.push('\\');
+        }
+        out.push_str(&digest.to_hex());
+        out.push_str("  ");
+        out.push_str(&escape(&rel));
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Verify every manifest entry against the files under `base`, like `sha256sum -c`.
+pub fn check(entries: &[ManifestEntry], base: &Path) -> Vec<CheckResult> {
+    use rayon::prelude::*;
+
+    entries
+        .par_iter()
+        .map(|m| {
+            let path = base.join(&m.path);
+            let status = if !path.is_file() {
+                Status::Missing
+            } else {
+                match hashing::hash_file(&path, m.digest.algo()) {
+                    Ok(d) if d == m.digest => Status::Ok,
+                    Ok(_) | Err(_) => Status::Failed,
+                }
+            };
+            CheckResult {
+                path: m.path.clone(),
+                status,
+            }
+        })
+        .collect()
+}
+
+/// Index manifest digests by path, for use as `ScanOptions::precomputed`.
+/// `./a.txt` and `a.txt` name the same file.
+pub fn to_precomputed(entries: &[ManifestEntry]) -> HashMap<PathBuf, Digest> {
+    entries
+        .iter()
+        .map(|m| {
+            let path = Path::new(&m.path)
+                .components()
+                .filter(|c| *c != Component::CurDir)
+                .collect();
+            (path, m.digest)
+        })
+        .collect()
+}
+
+fn escape(path: &str) -> String {
+    path.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(path: &str) -> Result<String> {
+    let mut out = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            other => bail!(
+                "bad escape sequence \\{}",
+                other.map(String::from).unwrap_or_default()
+            ),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_coreutils_output() {
+        // `printf 'Hello World\n' | sha256sum`, in text and binary mode.
+        let text = "d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26  a.txt\n\
+                    d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26 *dir/b c.txt\n\
+                    \n\
+                    \\d2a84f4b8b650937ec8f73cd8be2c74add5a911ba64df27458ed8229da804a26  odd\\\\name\\n.txt\n";
+        let entries = parse(text, Algo::Sha256).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].path, "a.txt");
+        assert_eq!(entries[1].path, "dir/b c.txt");
+        assert_eq!(entries[2].path, "odd\\name\n.txt");
+        assert_eq!(entries[0].digest.algo(), Algo::Sha256);
+    }
+
+    #[test]
+    fn test_precomputed_keys_drop_leading_dot() {
+        let text = "00000000000000ff  ./a.txt\n00000000000000ee  ./dir/./b.txt\n";
+        let index = to_precomputed(&parse(text, Algo::Xxh3).unwrap());
+        assert!(index.contains_key(Path::new("a.txt")));
+        assert!(index.contains_key(Path::new("dir/b.txt")));
+    }
+
+    #[test]
+    fn test_parse_rejects_wrong_length() {
+        let err = parse("abcd  a.txt\n", Algo::Blake3).unwrap_err();
+        assert!(err.to_string().starts_with("line 1"));
+    }
+
+    #[test]
+    fn test_render_then_check() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("ok.txt"), "Hello World\n").unwrap();
+        fs::write(dir.path().join("changed.txt"), "before").unwrap();
+        fs::write(dir.path().join("gone.txt"), "soon deleted").unwrap();
+
+        let entries: Vec<FileEntry> = ["ok.txt", "changed.txt", "gone.txt"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                FileEntry {
+                    hash: hashing::hash_file(&path, Algo::Blake3).unwrap(),
+                    ..FileEntry::test(&path.to_string_lossy(), "xxh3:0000000000000001")
+                }
+            })
+            .collect();
+        let text = render(&entries, dir.path(), Algo::Blake3).unwrap();
+        assert!(text
+            .lines()
+            .all(|l| !l.contains(&*dir.path().to_string_lossy())));
+
+        fs::write(dir.path().join("changed.txt"), "after").unwrap();
+        fs::remove_file(dir.path().join("gone.txt")).unwrap();
+
+        let results = check(&parse(&text, Algo::Blake3).unwrap(), dir.path());
+        let statuses: Vec<_> = results.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, vec![Status::Ok, Status::Failed, Status::Missing]);
+    }
+
+    #[test]
+    fn test_render_requires_matching_algo() {
+        let entry = FileEntry::test("x", "xxh3:0000000000000001");
+        assert!(render(&[entry], Path::new("."), Algo::Sha256).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+//! File attributes captured once, when the walk first stats a file.
+//!
+//! Reports and keeper decisions need ages and ownership, and stat-ing every
+//! file again afterwards is slow and racy. Fields a platform cannot tell are
+//! `None`, as they are in reports written before these were recorded.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::Metadata;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileMeta {
+    /// Last modification of the contents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<DateTime<Utc>>,
+    /// Last change of the contents or the inode, such as a rename or chmod.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ctime: Option<DateTime<Utc>>,
+    /// Creation. Read with statx on Linux, where the filesystem must record
+    /// it too.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub btime: Option<DateTime<Utc>>,
+    /// Permission and file type bits, as in `st_mode`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+}
+
+impl FileMeta {
+    pub fn from_metadata(md: &Metadata) -> Self {
+        // std asks statx for the birth time on Linux and falls back to an
+        // error where it is unavailable.
+        let mut meta = Self {
+            mtime: md.modified().ok().map(DateTime::from),
+            btime: md.created().ok().map(DateTime::from),
+            ..Default::default()
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            meta.ctime = DateTime::from_timestamp(md.ctime(), md.ctime_nsec() as u32);
+            meta.mode = Some(md.mode());
+            meta.uid = Some(md.uid());
+            meta.gid = Some(md.gid());
+        }
+        meta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_captures_times_and_owner() {
+        let file = NamedTempFile::new().unwrap();
+        let md = file.as_file().metadata().unwrap();
+        let meta = FileMeta::from_metadata(&md);
+        let mtime = meta.mtime.unwrap();
+        assert!((Utc::now() - mtime).num_minutes() < 5);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(meta.uid, Some(md.uid()));
+            assert_eq!(meta.mode.unwrap() & 0o170000, 0o100000);
+            assert!(meta.ctime.is_some());
+        }
+
+        let json = serde_json::to_string(&meta).unwrap();
+        assert_eq!(serde_json::from_str::<FileMeta>(&json).unwrap(), meta);
+        assert_eq!(
+            serde_json::from_str::<FileMeta>("{}").unwrap(),
+            FileMeta::default()
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Hooks for watching a scan as it runs.
+//!
+//! Progress bars, logging and embedding applications implement
+//! [`ScanObserver`] and pass it in `ScanOptions::observer`. Every callback
+//! defaults to doing nothing, and all of them may be called from several
+//! worker threads at once.
+
+use crate::{filtering::SkipReason, outcome::ScanError, FileEntry};
+use std::{fmt, path::Path};
+
+pub trait ScanObserver: Send + Sync {
+    /// The walk descended into a directory, the root included.
+    fn dir_entered(&self, _path: &Path) {}
+    /// A file passed the filter and will be considered for hashing.
+    fn file_matched(&self, _path: &Path, _size: u64) {}
+    /// A walked path was left out, and why.
+    fn file_skipped(&self, _path: &Path, _reason: SkipReason) {}
+    /// `n` more bytes went through a hasher. Called many times per file.
+    fn bytes_hashed(&self, _n: u64) {}
+    /// A file was fully hashed.
+    fn file_hashed(&self, _entry: &FileEntry) {}
+    /// A matched file was ruled out without being fully hashed, as the
+    /// staged search does with files whose size or head and tail are unique.
+    fn file_dropped(&self, _path: &Path) {}
+    /// A path could not be walked or read; it is also in the scan's errors.
+    fn error(&self, _err: &ScanError) {}
+}
+
+/// The default observer, which ignores everything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl ScanObserver for NoopObserver {}
+
+impl fmt::Debug for dyn ScanObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ScanObserver")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filtering::Filter, ScanOptions};
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    };
+
+    #[derive(Default)]
+    struct Counter {
+        dirs: AtomicU64,
+        matched: AtomicU64,
+        skipped: Mutex<Vec<SkipReason>>,
+        bytes: AtomicU64,
+        hashed: AtomicU64,
+        dropped: AtomicU64,
+    }
+
+    impl ScanObserver for Counter {
+        fn dir_entered(&self, _path: &Path) {
+            self.dirs.fetch_add(1, Ordering::Relaxed);
+        }
+        fn file_matched(&self, _path: &Path, _size: u64) {
+            self.matched.fetch_add(1, Ordering::Relaxed);
+        }
+        fn file_skipped(&self, _path: &Path, reason: SkipReason) {
+            self.skipped.lock().unwrap().push(reason);
+        }
+        fn bytes_hashed(&self, n: u64) {
+            self.bytes.fetch_add(n, Ordering::Relaxed);
+        }
+        fn file_hashed(&self, _entry: &FileEntry) {
+            self.hashed.fetch_add(1, Ordering::Relaxed);
+        }
+        fn file_dropped(&self, _path: &Path) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_observer_sees_the_whole_scan() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), vec![1u8; 100_000]).unwrap();
+        std::fs::write(dir.path().join("sub/b.txt"), "hello").unwrap();
+        std::fs::write(dir.path().join("c.log"), "left out").unwrap();
+
+        let filter = Filter {
+            ext: Some("txt".into()),
+            ..Filter::any()
+        };
+        let counter = Arc::new(Counter::default());
+        let opts = ScanOptions {
+            observer: counter.clone(),
+            ..Default::default()
+        };
+        let scan = crate::scan_with(dir.path(), &filter, &opts).unwrap();
+
+        assert_eq!(scan.entries.len(), 2);
+        assert_eq!(counter.dirs.load(Ordering::Relaxed), 2);
+        assert_eq!(counter.matched.load(Ordering::Relaxed), 2);
+        assert_eq!(*counter.skipped.lock().unwrap(), vec![SkipReason::Extension]);
+        assert_eq!(counter.bytes.load(Ordering::Relaxed), 100_005);
+        assert_eq!(counter.hashed.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_staged_search_accounts_for_every_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let big = vec![1u8; 100_000];
+        let mut tail = big.clone();
+        tail[99_999] = 2;
+        std::fs::write(dir.path().join("unique.txt"), "nobody else is this long").unwrap();
+        std::fs::write(dir.path().join("a.txt"), "abc").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "xyz").unwrap();
+        std::fs::write(dir.path().join("big1.bin"), &big).unwrap();
+        std::fs::write(dir.path().join("big2.bin"), &big).unwrap();
+        std::fs::write(dir.path().join("big3.bin"), &tail).unwrap();
+
+        let counter = Arc::new(Counter::default());
+        let opts = ScanOptions {
+            observer: counter.clone(),
+            ..Default::default()
+        };
+        crate::pipeline::find_duplicates(dir.path(), &Filter::any(), &opts).unwrap();
+
+        assert_eq!(counter.matched.load(Ordering::Relaxed), 6);
+        // unique.txt by size and big3.bin by its tail; the rest are hashed.
+        assert_eq!(counter.dropped.load(Ordering::Relaxed), 2);
+        assert_eq!(counter.hashed.load(Ordering::Relaxed), 4);
+    }
+}
@@ -0,0 +1,122 @@
+//! What a scan produced, including the paths it could not handle.
+//!
+//! Unreadable directories, files deleted mid-scan and symlink loops are
+//! expected on real trees. Rather than dropping them silently or aborting
+//! the run, every scan records them as [`ScanError`]s next to its entries.
+
+use crate::{cancel::StopReason, FileEntry};
+use serde::Serialize;
+use std::{fmt, io, path::PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub enum ScanErrorKind {
+    PermissionDenied,
+    /// The path disappeared between being listed and being read.
+    Vanished,
+    SymlinkLoop,
+    /// Any other I/O failure.
+    Io,
+}
+
+impl fmt::Display for ScanErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScanErrorKind::PermissionDenied => "permission denied",
+            ScanErrorKind::Vanished => "vanished",
+            ScanErrorKind::SymlinkLoop => "symlink loop",
+            ScanErrorKind::Io => "I/O error",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub kind: ScanErrorKind,
+    /// The underlying error, for humans.
+    pub message: String,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl ScanError {
+    pub(crate) fn from_io(path: PathBuf, err: &io::Error) -> Self {
+        let kind = match err.kind() {
+            io::ErrorKind::PermissionDenied => ScanErrorKind::PermissionDenied,
+            io::ErrorKind::NotFound => ScanErrorKind::Vanished,
+            _ => ScanErrorKind::Io,
+        };
+        Self {
+            path,
+            kind,
+            message: err.to_string(),
+        }
+    }
+
+    pub(crate) fn from_walk(err: &walkdir::Error) -> Self {
+        let path = err.path().map(PathBuf::from).unwrap_or_default();
+        if err.loop_ancestor().is_some() {
+            return Self {
+                path,
+                kind: ScanErrorKind::SymlinkLoop,
+                message: err.to_string(),
+            };
+        }
+        match err.io_error() {
+            Some(io) => Self::from_io(path, io),
+            None => Self {
+                path,
+                kind: ScanErrorKind::Io,
+                message: err.to_string(),
+            },
+        }
+    }
+
+    /// Classify a hashing failure by the I/O error at its root, if any.
+    pub(crate) fn from_anyhow(path: PathBuf, err: &anyhow::Error) -> Self {
+        match err.chain().find_map(|e| e.downcast_ref::<io::Error>()) {
+            Some(io) => Self {
+                message: err.to_string(),
+                ..Self::from_io(path, io)
+            },
+            None => Self {
+                path,
+                kind: ScanErrorKind::Io,
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+/// Entries of a scan together with the paths that failed.
+#[derive(Debug, Clone, Default)]
+pub struct ScanOutcome {
+    pub entries: Vec<FileEntry>,
+    pub errors: Vec<ScanError>,
+    /// Set when the scan was cancelled or ran out of time, in which case
+    /// `entries` and `errors` cover only part of the tree.
+    pub stopped: Option<StopReason>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_errors_are_classified() {
+        let e = ScanError::from_io("a".into(), &io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(e.kind, ScanErrorKind::Vanished);
+        let e = ScanError::from_anyhow(
+            "b".into(),
+            &anyhow::Error::from(io::Error::from(io::ErrorKind::PermissionDenied)),
+        );
+        assert_eq!(e.kind, ScanErrorKind::PermissionDenied);
+        let e = ScanError::from_anyhow("c".into(), &anyhow::anyhow!("digest mismatch"));
+        assert_eq!(e.kind, ScanErrorKind::Io);
+        assert_eq!(e.to_string(), "c: digest mismatch");
+    }
+}
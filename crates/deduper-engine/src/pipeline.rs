@@ -0,0 +1,287 @@
+//! Staged duplicate detection: size → partial hash → full hash.
+//!
+//! Only files that still collide after a stage are passed on to the next,
+//! so files with a unique size are never opened and most non-duplicates are
+//! rejected after reading a few KiB.
+
+use crate::{
+    cancel::StopReason,
+    devices,
+    filtering::Filter,
+    hashing,
+    order::{self, ReadOrder},
+    outcome::ScanError,
+    roots::ScanRoot,
+    Candidate, FileEntry, ScanOptions,
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{collections::HashMap, hash::Hash, path::Path};
+
+/// Bytes read from each end of a file during the partial-hash stage.
+pub const PARTIAL_WINDOW: u64 = 4096;
+
+/// How many files each stage let through or dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct StageStats {
+    /// Files that passed the filter.
+    pub scanned: usize,
+    /// Files whose size no other file shares.
+    pub dropped_at_size: usize,
+    /// Files whose head/tail hash no other same-size file shares, or that
+    /// could not be read.
+    pub dropped_at_partial: usize,
+    /// Files whose full hash turned out to be unique, or that could not be read.
+    pub dropped_at_full: usize,
+    /// Files confirmed as duplicates by the full hash.
+    pub confirmed: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StagedScan {
+    /// Confirmed duplicates only; files with a unique hash are left out.
+    pub entries: Vec<FileEntry>,
+    /// Files left out after a stop count as dropped at the stage it hit.
+    pub stats: StageStats,
+    /// Paths that could not be walked or read at any stage.
+    pub errors: Vec<ScanError>,
+    /// Set when the scan was cancelled or ran out of time. The entries are
+    /// still confirmed duplicates, but others may have been missed.
+    pub stopped: Option<StopReason>,
+}
+
+/// Find duplicate files under `root` while reading as few bytes as possible.
+///
+/// Only the final stage honours `opts.extra_algos` and `opts.precomputed`.
+/// Links listed under `SymlinkPolicy::Report` have no contents to compare
+/// and are left out.
+pub fn find_duplicates(root: &Path, filter: &Filter, opts: &ScanOptions) -> Result<StagedScan> {
+    find_duplicates_in(&[ScanRoot::candidate(root)], filter, opts)
+}
+
+/// [`find_duplicates`] across several roots, with every entry labelled with
+/// the role of its root.
+pub fn find_duplicates_in(
+    roots: &[ScanRoot],
+    filter: &Filter,
+    opts: &ScanOptions,
+) -> Result<StagedScan> {
+    let stop = opts.stop();
+    let (mut files, mut errors) = crate::matching_files(roots, filter, opts, &stop);
+    files.retain(|c| {
+        if c.symlink.is_some() {
+            opts.observer.file_dropped(&c.path);
+        }
+        c.symlink.is_none() && !c.members_only
+    });
+    let pools = devices::DevicePools::new(&opts.device_threads);
+    let mut stats = StageStats {
+        scanned: files.len(),
+        ..Default::default()
+    };
+
+    // Stage 1: group by exact size.
+    let dropped = |c: Candidate| opts.observer.file_dropped(&c.path);
+    let sized: Vec<_> = colliding(files, |c| c.size, dropped)
+        .into_iter()
+        .flatten()
+        .collect();
+    stats.dropped_at_size = stats.scanned - sized.len();
+
+    // Stage 2: hash the head and tail of every same-size candidate. Files
+    // no larger than the two windows would be read whole, so they skip
+    // straight to the full hash.
+    let (small, mut large): (Vec<_>, Vec<_>) = sized
+        .into_iter()
+        .partition(|c| c.size <= 2 * PARTIAL_WINDOW);
+    if opts.order == ReadOrder::Disk {
+        order::sort_by_disk(&mut large, |c| c);
+    }
+    let partial = pools.par_filter_map(
+        &large,
+        |c| c.dev,
+        |c| {
+            if stop.check() {
+                return None;
+            }
+            Some(
+                match hashing::hash_partial_with(&c.path, opts.algo, PARTIAL_WINDOW, &opts.io) {
+                    Ok(h) => {
+                        opts.observer.bytes_hashed(2 * PARTIAL_WINDOW);
+                        Ok((c.clone(), h))
+                    }
+                    Err(e) => {
+                        let err = ScanError::from_anyhow(c.path.clone(), &e);
+                        opts.observer.error(&err);
+                        Err(err)
+                    }
+                },
+            )
+        },
+    );
+    let partial = split_errors(partial, &mut errors);
+    let passed: Vec<_> = colliding(partial, |(c, h)| (c.size, *h), |(c, _)| dropped(c))
+        .into_iter()
+        .flatten()
+        .map(|(c, _)| c)
+        .collect();
+    stats.dropped_at_partial = large.len() - passed.len();
+    let mut survivors = small;
+    survivors.extend(passed);
+
+    // Stage 3: fully hash what is left.
+    if opts.order == ReadOrder::Disk {
+        order::sort_by_disk(&mut survivors, |c| c);
+    }
+    let full = pools.par_filter_map(
+        &survivors,
+        |c| c.dev,
+        |c| crate::hash_candidate(&roots[c.root], c, opts, &stop),
+    );
+    let full = split_errors(full, &mut errors);
+    // Files with a unique full hash were already reported as hashed.
+    let entries: Vec<FileEntry> = colliding(full, |e| e.hash, |_| {})
+        .into_iter()
+        .flatten()
+        .collect();
+    stats.confirmed = entries.len();
+    stats.dropped_at_full = survivors.len() - entries.len();
+
+    Ok(StagedScan {
+        entries,
+        stats,
+        errors,
+        stopped: stop.reason(),
+    })
+}
+
+/// Keep the successes, moving failures to `errors`.
+fn split_errors<T>(results: Vec<Result<T, ScanError>>, errors: &mut Vec<ScanError>) -> Vec<T> {
+    let mut ok = Vec::with_capacity(results.len());
+    for r in results {
+        match r {
+            Ok(t) => ok.push(t),
+            Err(e) => errors.push(e),
+        }
+    }
+    ok
+}
+
+/// Group `items` by `key` and keep only groups with more than one member,
+/// handing every item left alone to `dropped`.
+fn colliding<T, K, F, D>(items: Vec<T>, key: F, mut dropped: D) -> Vec<Vec<T>>
+where
+    K: Eq + Hash,
+    F: Fn(&T) -> K,
+    D: FnMut(T),
+{
+    let mut groups: HashMap<K, Vec<T>> = HashMap::new();
+    for item in items {
+        groups.entry(key(&item)).or_default().push(item);
+    }
+    let mut out = Vec::new();
+    for g in groups.into_values() {
+        if g.len() > 1 {
+            out.push(g);
+        } else {
+            g.into_iter().for_each(&mut dropped);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_stages_drop_non_duplicates() {
+        let temp_dir = TempDir::new().unwrap();
+        let big = vec![7u8; 3 * PARTIAL_WINDOW as usize];
+        let mut middle = big.clone();
+        middle[PARTIAL_WINDOW as usize + 1] = 8;
+        let mut tail = big.clone();
+        *tail.last_mut().unwrap() = 8;
+
+        fs::write(
+            temp_dir.path().join("unique.txt"),
+            "no one else is this long",
+        )
+        .unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "abc").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "xyz").unwrap();
+        fs::write(temp_dir.path().join("big1.bin"), &big).unwrap();
+        fs::write(temp_dir.path().join("big2.bin"), &big).unwrap();
+        fs::write(temp_dir.path().join("big3.bin"), &middle).unwrap();
+        fs::write(temp_dir.path().join("big4.bin"), &tail).unwrap();
+
+        let scan =
+            find_duplicates(temp_dir.path(), &Filter::any(), &ScanOptions::default()).unwrap();
+        assert_eq!(
+            scan.stats,
+            StageStats {
+                scanned: 7,
+                dropped_at_size: 1,
+                // Only big4; a.txt and b.txt are too small for a partial hash.
+                dropped_at_partial: 1,
+                dropped_at_full: 3,
+                confirmed: 2,
+            }
+        );
+        assert_eq!(scan.entries.len(), 2);
+        assert_eq!(scan.entries[0].hash, scan.entries[1].hash);
+        assert!(scan
+            .entries
+            .iter()
+            .all(|e| e.path.contains("big1") || e.path.contains("big2")));
+    }
+
+    #[test]
+    fn test_partial_hash_ignores_middle() {
+        let temp_dir = TempDir::new().unwrap();
+        let a = vec![1u8; 3 * PARTIAL_WINDOW as usize];
+        let mut b = a.clone();
+        b[PARTIAL_WINDOW as usize + 1] = 2;
+        fs::write(temp_dir.path().join("a"), &a).unwrap();
+        fs::write(temp_dir.path().join("b"), &b).unwrap();
+
+        let ha = hashing::hash_partial(
+            &temp_dir.path().join("a"),
+            hashing::Algo::Xxh3,
+            PARTIAL_WINDOW,
+        )
+        .unwrap();
+        let hb = hashing::hash_partial(
+            &temp_dir.path().join("b"),
+            hashing::Algo::Xxh3,
+            PARTIAL_WINDOW,
+        )
+        .unwrap();
+        assert_eq!(ha, hb);
+    }
+
+    #[test]
+    fn test_partial_hash_with_direct_io() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("odd");
+        let body: Vec<u8> = (0..3 * PARTIAL_WINDOW + 123).map(|i| i as u8).collect();
+        fs::write(&path, &body).unwrap();
+
+        let direct = crate::io::ReadOptions {
+            cache: crate::io::CachePolicy::Direct,
+            ..Default::default()
+        };
+        let plain = hashing::hash_partial(&path, hashing::Algo::Xxh3, PARTIAL_WINDOW).unwrap();
+        let with = hashing::hash_partial_with(&path, hashing::Algo::Xxh3, PARTIAL_WINDOW, &direct)
+            .unwrap();
+        assert_eq!(plain, with);
+
+        let w = PARTIAL_WINDOW as usize;
+        let mut sample = body[..w].to_vec();
+        sample.extend_from_slice(&body[body.len() - w..]);
+        let expected = hashing::hash_bytes(&sample, hashing::Algo::Xxh3).unwrap();
+        assert_eq!(plain, expected);
+    }
+}
@@ -5,7 +5,12 @@ use std::{
     path::{Path, PathBuf},
 };
 
+/// Move `src` into the quarantine directory. Links are refused: moving one
+/// would shelve the link and leave the file it stands for in place.
 pub fn quarantine(src: &Path) -> Result<PathBuf> {
+    if fs::symlink_metadata(src)?.file_type().is_symlink() {
+        anyhow::bail!("{} is a symbolic link", src.display());
+    }
     let qdir = home_dir()
         .ok_or_else(|| anyhow::anyhow!("cannot resolve $HOME"))?
         .join(".deduper/quarantine");
@@ -52,6 +57,19 @@ mod tests {
         assert!(!quarantined_path.exists());
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_quarantine_refuses_symlink() {
+        let temp_dir = TempDir::new().unwrap();
+        let target = temp_dir.path().join("target.txt");
+        fs::write(&target, "kept").unwrap();
+        let link = temp_dir.path().join("link.txt");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        assert!(quarantine(&link).is_err());
+        assert!(link.exists() && target.exists());
+    }
+
     #[test]
     fn test_recover_nonexistent_file() {
         let result = recover("nonexistent_file.txt");
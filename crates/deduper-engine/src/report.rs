@@ -1,17 +1,83 @@
+//! A static HTML page listing duplicate groups.
+
+use crate::{hardlinks, FileEntry};
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Write `groups` to `path` as one page, using the size, times and owner
+/// each entry was scanned with rather than stat-ing files again.
 pub fn html(groups: &[Vec<&FileEntry>], path: &Path) -> anyhow::Result<()> {
     let mut out = String::new();
     out.push_str("<!doctype html><title>Deduper Report</title><style>body{font-family:sans-serif}</style>");
-    let mut saved = 0u64;
     for g in groups {
-        let size = std::fs::metadata(&g[0].path)?.len();
-        saved += size * (g.len() as u64 - 1);
-        out.push_str(&format!("<h3>{} duplicates ({} bytes each)</h3><ul>",
-                              g.len(), size));
-        for f in g { out.push_str(&format!("<li>{}</li>", f.path)); }
+        let size = g[0].size;
+        write!(out, "<h3>{} duplicates ({} bytes each)</h3><ul>", g.len(), size)?;
+        // Archives holding a copy, named on the files that could go.
+        let mut archives: Vec<&str> = Vec::new();
+        for a in g.iter().filter_map(|f| f.archive.as_deref()) {
+            if !archives.contains(&a) {
+                archives.push(a);
+            }
+        }
+        let inside = escape(&archives.join(", "));
+        for f in g {
+            write!(out, "<li>{}", escape(&f.path))?;
+            if let Some(mtime) = f.meta.mtime {
+                write!(out, " <small>modified {}</small>", mtime.format("%Y-%m-%d %H:%M"))?;
+            }
+            if let Some(uid) = f.meta.uid {
+                write!(out, " <small>uid {}</small>", uid)?;
+            }
+            if f.archive.is_none() && !archives.is_empty() {
+                write!(out, " <small>already inside {}</small>", inside)?;
+            }
+            out.push_str("</li>");
+        }
         out.push_str("</ul>");
     }
-    out.push_str(&format!("<hr><b>Potential savings: {} MB</b>",
-                          saved / 1_048_576));
+    // Hardlinks and links outside the scan free nothing when removed.
+    let saved = hardlinks::reclaimable_bytes(groups);
+    write!(out, "<hr><b>Potential savings: {} MB</b>", saved / 1_048_576)?;
     std::fs::write(path, out)?;
     Ok(())
 }
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_html_uses_scanned_metadata() {
+        let temp_dir = TempDir::new().unwrap();
+        let entry = |path: &str| FileEntry {
+            size: 3 << 20,
+            meta: crate::metadata::FileMeta {
+                mtime: "2024-05-01T12:30:00Z".parse().ok(),
+                ..Default::default()
+            },
+            ..FileEntry::test(path, "xxh3:0000000000000001")
+        };
+        // Neither file exists, so nothing can have been read from disk.
+        let member = FileEntry {
+            archive: Some("gone/old.zip".into()),
+            ..entry("gone/old.zip!/d.bin")
+        };
+        let entries = [entry("gone/a&b.bin"), entry("gone/c.bin"), member];
+        let out = temp_dir.path().join("report.html");
+        html(&[entries.iter().collect()], &out).unwrap();
+
+        let page = std::fs::read_to_string(&out).unwrap();
+        assert!(page.contains("3 duplicates (3145728 bytes each)"));
+        assert!(page.contains("gone/a&amp;b.bin <small>modified 2024-05-01 12:30</small> \
+                               <small>already inside gone/old.zip</small></li>"));
+        assert!(page.contains("gone/c.bin <small>modified 2024-05-01 12:30</small> \
+                               <small>already inside gone/old.zip</small></li>"));
+        assert!(page.contains("gone/old.zip!/d.bin <small>modified 2024-05-01 12:30</small></li>"));
+        assert!(page.contains("Potential savings: 3 MB"));
+    }
+}
@@ -0,0 +1,472 @@
+//! Resumable hashing of very large files.
+//!
+//! Hashing a multi-terabyte disk image takes hours, and starting over after
+//! an interruption wastes all of them. Files are instead hashed in segments
+//! of [`CHECKPOINT_EVERY`] bytes; after each segment the hasher state is saved
+//! to the [`Index`], and a later run that finds a checkpoint for the same,
+//! unmodified file continues right after the last finished segment.
+//!
+//! Only algorithms whose state can be captured are resumable: SHA-256 (its
+//! eight state words) and BLAKE3 (the chaining values of finished subtrees).
+//! xxh3 keeps its state private and is always hashed in one go.
+
+use crate::{
+    db::Index,
+    hashing::{Algo, Digest, FileHash},
+    cancel::{Interrupted, Stop},
+    io::{self, ReadOptions},
+    observer::{NoopObserver, ScanObserver},
+};
+use anyhow::{bail, Result};
+use blake3::hazmat::{
+    merge_subtrees_non_root, merge_subtrees_root, ChainingValue, HasherExt, Mode,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, io::Read, path::Path};
+
+/// Segment size, and thus how much work an interruption can lose. A power of
+/// two, as BLAKE3 subtrees must be, and 4 KiB aligned for `O_DIRECT`.
+pub const CHECKPOINT_EVERY: u64 = 256 * 1024 * 1024;
+
+/// Progress through one file, as stored in the index.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Size and modification time (ns) of the file the progress belongs to.
+    pub len: u64,
+    pub mtime: i64,
+    /// Bytes hashed so far; a whole number of segments.
+    pub offset: u64,
+    pub all_zero: bool,
+    /// One per algorithm, in the order requested.
+    pub states: Vec<State>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum State {
+    /// SHA-256 state words after `offset` bytes.
+    Sha256([u32; 8]),
+    /// BLAKE3 chaining values of the finished subtrees, leftmost first.
+    Blake3(Vec<ChainingValue>),
+}
+
+/// Whether `algo` can be checkpointed; false for xxh3.
+pub fn is_resumable(algo: Algo) -> bool {
+    matches!(algo, Algo::Sha256 | Algo::Blake3)
+}
+
+/// Like [`hashing::hash_file_multi`](crate::hashing::hash_file_multi), but
+/// saving progress to `index` after every segment and starting from an
+/// earlier checkpoint if one fits. Every algorithm must be resumable.
+pub fn hash_file(path: &Path, algos: &[Algo], io: &ReadOptions, index: &Index) -> Result<FileHash> {
+    hash_segmented(path, algos, io, index, CHECKPOINT_EVERY, &NoopObserver, &Stop::never())
+}
+
+/// [`hash_file`], telling `observer` about every block hashed. Once `stop`
+/// fires it gives up with [`Interrupted`], keeping the last checkpoint.
+pub(crate) fn hash_file_observed(
+    path: &Path,
+    algos: &[Algo],
+    io: &ReadOptions,
+    index: &Index,
+    observer: &dyn ScanObserver,
+    stop: &Stop,
+) -> Result<FileHash> {
+    hash_segmented(path, algos, io, index, CHECKPOINT_EVERY, observer, stop)
+}
+
+fn hash_segmented(
+    path: &Path,
+    algos: &[Algo],
+    io: &ReadOptions,
+    index: &Index,
+    seg: u64,
+    observer: &dyn ScanObserver,
+    stop: &Stop,
+) -> Result<FileHash> {
+    if let Some(a) = algos.iter().find(|&&a| !is_resumable(a)) {
+        bail!("{} hashing cannot be checkpointed", a);
+    }
+    let md = fs::metadata(path)?;
+    let (len, mtime) = (md.len(), mtime_nanos(&md));
+    let mut cp = index
+        .checkpoint(path)
+        .filter(|c| {
+            c.len == len
+                && c.mtime == mtime
+                && c.offset % seg == 0
+                && c.states.iter().map(State::algo).eq(algos.iter().copied())
+        })
+        .unwrap_or_else(|| Checkpoint {
+            len,
+            mtime,
+            offset: 0,
+            all_zero: true,
+            states: algos.iter().map(|&a| State::new(a)).collect(),
+        });
+
+    let mut src = io::open(path, io)?;
+    src.seek_to(cp.offset)?;
+    let mut buf = io.buffer();
+    let buf = buf.as_mut_slice();
+    loop {
+        let mut segment: Vec<_> = cp
+            .states
+            .iter()
+            .map(|s| Segment::new(s, cp.offset))
+            .collect();
+        let mut filled = 0u64;
+        while filled < seg {
+            if stop.check() {
+                return Err(Interrupted.into());
+            }
+            let want = buf.len().min((seg - filled) as usize);
+            let n = src.read(&mut buf[..want])?;
+            if n == 0 {
+                break;
+            }
+            for s in &mut segment {
+                s.update(&buf[..n]);
+            }
+            observer.bytes_hashed(n as u64);
+            filled += n as u64;
+        }
+        let all_zero = cp.all_zero && src.all_zero();
+
+        if filled == seg && cp.offset + seg < len {
+            for (state, s) in cp.states.iter_mut().zip(segment) {
+                s.fold_into(state, cp.offset / seg);
+            }
+            cp.offset += seg;
+            cp.all_zero = all_zero;
+            index.save_checkpoint(path, &cp)?;
+            continue;
+        }
+
+        index.clear_checkpoint(path);
+        if filled == 0 && cp.offset > 0 {
+            bail!("{} shrank while being hashed", path.display());
+        }
+        let digests = segment
+            .into_iter()
+            .zip(cp.states)
+            .map(|(s, state)| s.finish(state))
+            .collect::<Result<_>>()?;
+        return Ok(FileHash { digests, all_zero });
+    }
+}
+
+pub(crate) fn mtime_nanos(md: &fs::Metadata) -> i64 {
+    md.modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos() as i64)
+}
+
+impl State {
+    fn new(algo: Algo) -> Self {
+        match algo {
+            Algo::Sha256 => State::Sha256(SHA256_IV),
+            _ => State::Blake3(Vec::new()),
+        }
+    }
+
+    fn algo(&self) -> Algo {
+        match self {
+            State::Sha256(_) => Algo::Sha256,
+            State::Blake3(_) => Algo::Blake3,
+        }
+    }
+}
+
+/// Hasher for the segment currently being read.
+enum Segment {
+    Sha256(Sha256Run),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Segment {
+    fn new(state: &State, offset: u64) -> Self {
+        match state {
+            State::Sha256(h) => Segment::Sha256(Sha256Run {
+                h: *h,
+                len: offset,
+                block: [0; 64],
+                filled: 0,
+            }),
+            State::Blake3(_) => {
+                let mut h = blake3::Hasher::new();
+                h.set_input_offset(offset);
+                Segment::Blake3(Box::new(h))
+            }
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Segment::Sha256(r) => r.update(data),
+            Segment::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    /// Merge the finished segment number `index` into `state`, knowing more
+    /// input follows it.
+    fn fold_into(self, state: &mut State, index: u64) {
+        match (self, state) {
+            (Segment::Sha256(r), State::Sha256(h)) => *h = r.h,
+            (Segment::Blake3(hasher), State::Blake3(stack)) => {
+                // The same lazy merging BLAKE3 does per chunk, per segment.
+                let mut cv = hasher.finalize_non_root();
+                let mut total = index + 1;
+                while total & 1 == 0 {
+                    let left = stack.pop().expect("subtree stack underflow");
+                    cv = merge_subtrees_non_root(&left, &cv, Mode::Hash);
+                    total >>= 1;
+                }
+                stack.push(cv);
+            }
+            _ => unreachable!("segment and state built from the same algorithm"),
+        }
+    }
+
+    /// Finish with this as the last segment of the file.
+    fn finish(self, state: State) -> Result<Digest> {
+        match (self, state) {
+            (Segment::Sha256(r), State::Sha256(_)) => Digest::from_slice(Algo::Sha256, &r.finish()),
+            (Segment::Blake3(hasher), State::Blake3(stack)) => {
+                let Some((first, rest)) = stack.split_first() else {
+                    return Digest::from_slice(Algo::Blake3, hasher.finalize().as_bytes());
+                };
+                let mut cv = hasher.finalize_non_root();
+                for left in rest.iter().rev() {
+                    cv = merge_subtrees_non_root(left, &cv, Mode::Hash);
+                }
+                let root = merge_subtrees_root(first, &cv, Mode::Hash);
+                Digest::from_slice(Algo::Blake3, root.as_bytes())
+            }
+            _ => unreachable!("segment and state built from the same algorithm"),
+        }
+    }
+}
+
+const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Streaming SHA-256 over the bare compression function, so that the state
+/// can be read out and restored, which `sha2::Sha256` does not allow.
+struct Sha256Run {
+    h: [u32; 8],
+    /// Bytes fed since the start of the file.
+    len: u64,
+    block: [u8; 64],
+    filled: usize,
+}
+
+impl Sha256Run {
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        if self.filled > 0 {
+            let take = (64 - self.filled).min(data.len());
+            self.block[self.filled..self.filled + take].copy_from_slice(&data[..take]);
+            self.filled += take;
+            data = &data[take..];
+            if self.filled < 64 {
+                return;
+            }
+            compress(&mut self.h, &self.block);
+            self.filled = 0;
+        }
+        let mut blocks = data.chunks_exact(64);
+        for b in &mut blocks {
+            compress(&mut self.h, b);
+        }
+        let rest = blocks.remainder();
+        self.block[..rest.len()].copy_from_slice(rest);
+        self.filled = rest.len();
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let mut tail = [0u8; 128];
+        tail[..self.filled].copy_from_slice(&self.block[..self.filled]);
+        tail[self.filled] = 0x80;
+        let end = if self.filled < 56 { 64 } else { 128 };
+        tail[end - 8..end].copy_from_slice(&self.len.wrapping_mul(8).to_be_bytes());
+        for b in tail[..end].chunks_exact(64) {
+            compress(&mut self.h, b);
+        }
+        let mut out = [0u8; 32];
+        for (o, w) in out.chunks_exact_mut(4).zip(self.h) {
+            o.copy_from_slice(&w.to_be_bytes());
+        }
+        out
+    }
+}
+
+fn compress(h: &mut [u32; 8], block: &[u8]) {
+    use sha2::digest::generic_array::GenericArray;
+    sha2::compress256(h, std::slice::from_ref(GenericArray::from_slice(block)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing;
+    use tempfile::TempDir;
+
+    const SEG: u64 = 4096;
+
+    fn noise(len: usize) -> Vec<u8> {
+        (0..len as u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect()
+    }
+
+    /// Hash in `SEG`-sized segments, unobserved and never stopped.
+    fn segmented(path: &Path, algos: &[Algo], index: &Index) -> Result<FileHash> {
+        let io = ReadOptions::default();
+        hash_segmented(path, algos, &io, index, SEG, &NoopObserver, &Stop::never())
+    }
+
+    #[test]
+    fn test_segmented_digest_matches_plain() {
+        let dir = TempDir::new().unwrap();
+        let index = Index::open(dir.path()).unwrap();
+        let algos = [Algo::Sha256, Algo::Blake3];
+        let seg = SEG as usize;
+        for len in [
+            0,
+            1,
+            55,
+            64,
+            seg - 1,
+            seg,
+            seg + 1,
+            3 * seg,
+            5 * seg + 100,
+            8 * seg,
+        ] {
+            let path = dir.path().join(format!("f{}", len));
+            fs::write(&path, noise(len)).unwrap();
+            let got = segmented(&path, &algos, &index).unwrap();
+            for (algo, digest) in algos.iter().zip(&got.digests) {
+                assert_eq!(
+                    *digest,
+                    hashing::hash_file(&path, *algo).unwrap(),
+                    "{} {}",
+                    algo,
+                    len
+                );
+            }
+            assert_eq!(got.all_zero, len <= 1);
+            assert!(index.checkpoint(&path).is_none());
+        }
+    }
+
+    #[test]
+    fn test_resumes_from_checkpoint() {
+        let dir = TempDir::new().unwrap();
+        let index = Index::open(dir.path()).unwrap();
+        let path = dir.path().join("image.bin");
+        let data = noise(6 * SEG as usize + 10);
+        fs::write(&path, &data).unwrap();
+        let md = fs::metadata(&path).unwrap();
+
+        // Pretend an earlier run hashed the first four segments, but of
+        // different bytes: resuming must then give the digest of those bytes
+        // followed by the real remainder, proving they were not re-read.
+        let mut other = vec![7u8; 4 * SEG as usize];
+        let mut states = vec![State::new(Algo::Sha256), State::new(Algo::Blake3)];
+        for i in 0..4 {
+            let part = &other[(i * SEG) as usize..][..SEG as usize];
+            for state in &mut states {
+                let mut s = Segment::new(state, i * SEG);
+                s.update(part);
+                s.fold_into(state, i);
+            }
+        }
+        let cp = Checkpoint {
+            len: md.len(),
+            mtime: mtime_nanos(&md),
+            offset: 4 * SEG,
+            all_zero: false,
+            states,
+        };
+        index.save_checkpoint(&path, &cp).unwrap();
+        assert_eq!(index.checkpoint(&path), Some(cp));
+
+        let algos = [Algo::Sha256, Algo::Blake3];
+        let got = segmented(&path, &algos, &index).unwrap();
+        other.extend_from_slice(&data[4 * SEG as usize..]);
+        let spliced = dir.path().join("spliced.bin");
+        fs::write(&spliced, &other).unwrap();
+        for (algo, digest) in algos.iter().zip(&got.digests) {
+            assert_eq!(*digest, hashing::hash_file(&spliced, *algo).unwrap());
+        }
+        assert!(index.checkpoint(&path).is_none());
+    }
+
+    #[test]
+    fn test_stale_checkpoint_is_ignored() {
+        let dir = TempDir::new().unwrap();
+        let index = Index::open(dir.path()).unwrap();
+        let path = dir.path().join("image.bin");
+        fs::write(&path, noise(3 * SEG as usize)).unwrap();
+        let cp = Checkpoint {
+            len: 3 * SEG,
+            mtime: 1,
+            offset: SEG,
+            all_zero: true,
+            states: vec![State::Sha256([0; 8])],
+        };
+        index.save_checkpoint(&path, &cp).unwrap();
+
+        let got = segmented(&path, &[Algo::Sha256], &index);
+        assert_eq!(
+            got.unwrap().digests[0],
+            hashing::hash_file(&path, Algo::Sha256).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_cancel_keeps_last_checkpoint() {
+        use crate::cancel::CancelToken;
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        struct CancelAfter(CancelToken, AtomicU64);
+        impl ScanObserver for CancelAfter {
+            fn bytes_hashed(&self, n: u64) {
+                if self.1.fetch_add(n, Ordering::Relaxed) + n >= 2 * SEG {
+                    self.0.cancel();
+                }
+            }
+        }
+
+        let dir = TempDir::new().unwrap();
+        let index = Index::open(dir.path()).unwrap();
+        let path = dir.path().join("image.bin");
+        fs::write(&path, noise(5 * SEG as usize)).unwrap();
+        let token = CancelToken::new();
+        let observer = CancelAfter(token.clone(), AtomicU64::new(0));
+        let stop = Stop::new(&token, None);
+
+        let io = ReadOptions::default();
+        let err = hash_segmented(&path, &[Algo::Blake3], &io, &index, SEG, &observer, &stop)
+            .unwrap_err();
+        assert!(crate::cancel::is_interrupted(&err));
+        assert_eq!(index.checkpoint(&path).unwrap().offset, 2 * SEG);
+
+        let got = segmented(&path, &[Algo::Blake3], &index).unwrap();
+        assert_eq!(got.digests[0], hashing::hash_file(&path, Algo::Blake3).unwrap());
+    }
+
+    #[test]
+    fn test_xxh3_is_not_resumable() {
+        let dir = TempDir::new().unwrap();
+        let index = Index::open(dir.path()).unwrap();
+        let path = dir.path().join("f");
+        fs::write(&path, "x").unwrap();
+        assert!(hash_file(&path, &[Algo::Xxh3], &ReadOptions::default(), &index).is_err());
+    }
+}
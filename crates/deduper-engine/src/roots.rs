@@ -0,0 +1,133 @@
+//! Scanning several trees at once, some of them only for comparison.
+//!
+//! "Which files in ~/Downloads are already in /archive?" is a scan of both
+//! with /archive as a [`RootRole::Reference`] root: its files take part in
+//! grouping and are preferred as the copy to keep, but are never picked for
+//! removal. A root nested in another is walked once, as its own root,
+//! except that a candidate root inside a reference root is all reference.
+
+use crate::{hardlinks, FileEntry};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RootRole {
+    /// Files here may be picked for removal.
+    #[default]
+    Candidate,
+    /// Files here are only compared against, and always kept.
+    Reference,
+}
+
+impl RootRole {
+    pub fn is_candidate(&self) -> bool {
+        *self == RootRole::Candidate
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanRoot {
+    pub path: PathBuf,
+    pub role: RootRole,
+}
+
+impl ScanRoot {
+    pub fn candidate(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            role: RootRole::Candidate,
+        }
+    }
+
+    pub fn reference(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            role: RootRole::Reference,
+        }
+    }
+}
+
+/// The index of the root whose walk covers `path`: the innermost root it is
+/// under, but any reference root wins over candidate roots, so nothing under
+/// a reference root is ever picked for removal.
+pub(crate) fn owner(roots: &[ScanRoot], path: &Path) -> Option<usize> {
+    roots
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| path.starts_with(&r.path))
+        .max_by_key(|(_, r)| (!r.role.is_candidate(), r.path.components().count()))
+        .map(|(i, _)| i)
+}
+
+fn is_reference(copy: &[&FileEntry]) -> bool {
+    copy.iter().any(|e| e.role == RootRole::Reference)
+}
+
+/// The entry that stays when a duplicate group is cleaned up: the first one
+/// under a reference root, or else the first one on disk, not inside an
+/// archive.
+pub fn keeper<'a>(group: &[&'a FileEntry]) -> &'a FileEntry {
+    group
+        .iter()
+        .find(|e| e.role == RootRole::Reference)
+        .or_else(|| group.iter().find(|e| e.archive.is_none()))
+        .unwrap_or(&group[0])
+}
+
+/// The physical copies in a duplicate group that may be removed. Copies with
+/// any path under a reference root stay, as does the first copy when there
+/// is no such root. Archive members are never included, and do not count as
+/// a kept copy either, since the archive may go away.
+pub fn removable_copies<'a>(group: &[&'a FileEntry]) -> Vec<Vec<&'a FileEntry>> {
+    let mut copies = hardlinks::physical_copies(group);
+    copies.retain(|c| c[0].archive.is_none());
+    if !copies.iter().any(|c| is_reference(c)) && !copies.is_empty() {
+        copies.remove(0);
+    }
+    copies.retain(|c| !is_reference(c));
+    copies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, role: RootRole) -> FileEntry {
+        FileEntry {
+            size: 1,
+            role,
+            ..FileEntry::test(path, "xxh3:0000000000000001")
+        }
+    }
+
+    #[test]
+    fn test_reference_copies_are_kept() {
+        let entries = [
+            entry("dl/a", RootRole::Candidate),
+            entry("archive/a", RootRole::Reference),
+            entry("dl/b", RootRole::Candidate),
+            entry("archive/b", RootRole::Reference),
+        ];
+        let group: Vec<&FileEntry> = entries.iter().collect();
+        assert_eq!(keeper(&group).path, "archive/a");
+        let gone: Vec<_> = removable_copies(&group)
+            .iter()
+            .map(|c| c[0].path.as_str())
+            .collect();
+        assert_eq!(gone, ["dl/a", "dl/b"]);
+
+        let candidates: Vec<&FileEntry> = vec![&entries[0], &entries[2]];
+        assert_eq!(keeper(&candidates).path, "dl/a");
+        assert_eq!(removable_copies(&candidates).len(), 1);
+        assert!(removable_copies(&[&entries[1], &entries[3]]).is_empty());
+
+        let member = FileEntry {
+            archive: Some("old.zip".into()),
+            ..entry("old.zip!/a", RootRole::Candidate)
+        };
+        assert_eq!(keeper(&[&member, &entries[0]]).path, "dl/a");
+        assert!(removable_copies(&[&member, &entries[0]]).is_empty());
+        let gone = removable_copies(&[&entries[0], &member, &entries[2]]);
+        assert_eq!(gone, [vec![&entries[2]]]);
+    }
+}
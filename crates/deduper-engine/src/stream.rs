@@ -0,0 +1,210 @@
+//! Scanning with bounded memory, yielding entries as they are hashed.
+//!
+//! [`scan_with`](crate::scan_with) walks the whole tree before hashing and
+//! returns everything at the end, which on tens of millions of files costs a
+//! lot of memory and leaves the caller waiting. [`scan`] instead walks on one
+//! thread, hashes batches of [`BATCH`] files as they are found, and hands out
+//! each [`FileEntry`] through a bounded channel as soon as its batch is done.
+//! Paths that fail come through the same channel as [`ScanError`]s.
+
+use crate::{
+    archives,
+    cancel::{Stop, StopReason},
+    devices,
+    filtering::Filter,
+    hash_candidate, order,
+    outcome::ScanError,
+    roots::ScanRoot,
+    walk_candidates, FileEntry, ScanOptions,
+};
+use std::{
+    path::Path,
+    sync::{
+        mpsc::{sync_channel, Receiver},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+/// Files walked and hashed together. This bounds memory use, and is the unit
+/// within which `ScanOptions::order` is applied.
+pub const BATCH: usize = 4096;
+
+/// Entries of a running scan, in no particular order. Dropping it stops the
+/// scan after the batch in progress; `ScanOptions::cancel` stops it sooner.
+pub struct ScanStream {
+    rx: Receiver<Result<FileEntry, ScanError>>,
+    workers: Vec<JoinHandle<()>>,
+    stop: Stop,
+}
+
+/// Like [`scan_with`](crate::scan_with), but returning at once and yielding
+/// entries while the scan is still running.
+pub fn scan(root: &Path, filter: &Filter, opts: &ScanOptions) -> ScanStream {
+    scan_roots(&[ScanRoot::candidate(root)], filter, opts)
+}
+
+/// [`scan`] over several roots, like [`scan_roots`](crate::scan_roots).
+pub fn scan_roots(roots: &[ScanRoot], filter: &Filter, opts: &ScanOptions) -> ScanStream {
+    let roots: Arc<[ScanRoot]> = roots.into();
+    let (batch_tx, batch_rx) = sync_channel::<Vec<crate::Candidate>>(1);
+    let (tx, rx) = sync_channel(BATCH);
+    let stop = opts.stop();
+    let opts = Arc::new(opts.clone());
+
+    let walker = {
+        let (roots, filter, tx) = (roots.clone(), filter.clone(), tx.clone());
+        let (opts, stop) = (opts.clone(), stop.clone());
+        std::thread::spawn(move || {
+            let mut batch = Vec::with_capacity(BATCH);
+            for r in walk_candidates(&roots, &filter, &opts, &stop) {
+                match r {
+                    Ok(c) => batch.push(c),
+                    Err(e) => {
+                        if tx.send(Err(e)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                if batch.len() == BATCH {
+                    let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH));
+                    if batch_tx.send(full).is_err() {
+                        return;
+                    }
+                }
+            }
+            if !batch.is_empty() {
+                let _ = batch_tx.send(batch);
+            }
+        })
+    };
+
+    let hasher = {
+        let (roots, filter, opts) = (roots.clone(), filter.clone(), opts.clone());
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            let pools = devices::DevicePools::new(&opts.device_threads);
+            for mut batch in batch_rx {
+                if opts.order == order::ReadOrder::Disk {
+                    order::sort_by_disk(&mut batch, |c| c);
+                }
+                let entries = pools.par_filter_map(
+                    &batch,
+                    |c| c.dev,
+                    |c| hash_candidate(&roots[c.root], c, &opts, &stop),
+                );
+                let members = archives::member_entries(&batch, &roots, &filter, &opts, &pools, &stop);
+                for e in entries.into_iter().chain(members) {
+                    if tx.send(e).is_err() {
+                        return;
+                    }
+                }
+            }
+        })
+    };
+
+    ScanStream {
+        rx,
+        workers: vec![walker, hasher],
+        stop,
+    }
+}
+
+impl ScanStream {
+    /// Why the scan ended early, if it did. Only final once the stream has
+    /// been drained.
+    pub fn stopped(&self) -> Option<StopReason> {
+        self.stop.reason()
+    }
+}
+
+impl Iterator for ScanStream {
+    type Item = Result<FileEntry, ScanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rx.recv() {
+            Ok(e) => Some(e),
+            Err(_) => {
+                // Both threads are done; surface a panic rather than
+                // passing off a truncated scan as complete.
+                for w in self.workers.drain(..) {
+                    if let Err(p) = w.join() {
+                        std::panic::resume_unwind(p);
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_stream_yields_same_entries_as_scan_with() {
+        let dir = TempDir::new().unwrap();
+        for i in 0..(BATCH + 10) {
+            fs::write(dir.path().join(format!("f{}", i)), format!("{}", i % 100)).unwrap();
+        }
+        let opts = ScanOptions {
+            algo: crate::hashing::Algo::Xxh3,
+            ..Default::default()
+        };
+
+        let mut streamed: Vec<_> = scan(dir.path(), &Filter::any(), &opts)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let mut collected = crate::scan_with(dir.path(), &Filter::any(), &opts)
+            .unwrap()
+            .entries;
+        streamed.sort_by(|a, b| a.path.cmp(&b.path));
+        collected.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(streamed.len(), BATCH + 10);
+        assert_eq!(streamed, collected);
+    }
+
+    #[derive(Default)]
+    struct CountHashed(AtomicUsize);
+
+    impl crate::observer::ScanObserver for CountHashed {
+        fn file_hashed(&self, _entry: &FileEntry) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_dropping_stream_stops_scan() {
+        let dir = TempDir::new().unwrap();
+        let total = 4 * BATCH;
+        for i in 0..total {
+            fs::write(dir.path().join(format!("f{}", i)), "x").unwrap();
+        }
+        let counter = Arc::new(CountHashed::default());
+        let opts = ScanOptions {
+            observer: counter.clone(),
+            ..Default::default()
+        };
+        let mut stream = scan(dir.path(), &Filter::any(), &opts);
+        assert!(stream.next().is_some());
+        let workers = std::mem::take(&mut stream.workers);
+        drop(stream);
+
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for w in workers {
+                w.join().unwrap();
+            }
+            let _ = done_tx.send(());
+        });
+        done_rx
+            .recv_timeout(std::time::Duration::from_secs(30))
+            .expect("scan kept running after the stream was dropped");
+        // The batch whose entries were being read, and at most the next.
+        assert!(counter.0.load(Ordering::SeqCst) <= 2 * BATCH);
+    }
+}
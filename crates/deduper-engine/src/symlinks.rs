@@ -0,0 +1,65 @@
+//! What a scan does with symbolic links.
+//!
+//! Following links makes one file reachable under several paths, and two of
+//! those paths hash the same without being copies of each other. Entries
+//! reached that way are marked with `FileEntry::via_symlink` so that callers
+//! never act on them, and [`quarantine`](crate::quarantine::quarantine)
+//! refuses to move a link itself.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Leave links out of the scan, reporting them as skipped.
+    #[default]
+    Skip,
+    /// Scan what links point to. Loops are reported as errors, not walked.
+    Follow,
+    /// List each link as an entry of its own, hashed over its target path,
+    /// without following it.
+    Report,
+}
+
+/// Tracks, during a depth-first walk, whether the current entry lies below
+/// a followed directory link.
+#[derive(Debug, Default)]
+pub(crate) struct LinkDepth {
+    /// Depth of the outermost linked directory being walked, if any.
+    linked_at: Option<usize>,
+}
+
+impl LinkDepth {
+    /// Whether the walked entry at `depth` was reached through a link,
+    /// either because it is one or because an ancestor below the root is.
+    /// Must be called for every entry, in walk order.
+    pub(crate) fn visit(&mut self, depth: usize, is_link: bool, is_dir: bool) -> bool {
+        if self.linked_at.is_some_and(|d| depth <= d) {
+            self.linked_at = None;
+        }
+        if self.linked_at.is_some() {
+            return true;
+        }
+        let is_link = is_link && depth > 0;
+        if is_link && is_dir {
+            self.linked_at = Some(depth);
+        }
+        is_link
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_depth_covers_subtree() {
+        let mut links = LinkDepth::default();
+        // root, dir a, linked dir b with two levels below it, then c.
+        assert!(!links.visit(0, true, true));
+        assert!(!links.visit(1, false, true));
+        assert!(links.visit(1, true, true));
+        assert!(links.visit(2, false, true));
+        assert!(links.visit(3, false, false));
+        assert!(!links.visit(1, false, false));
+        assert!(links.visit(1, true, false));
+        assert!(!links.visit(1, false, false));
+    }
+}
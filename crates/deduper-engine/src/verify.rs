@@ -0,0 +1,287 @@
+//! Byte-for-byte confirmation of hash-equal files.
+//!
+//! Equal digests are strong evidence but not proof, least of all with the
+//! 64-bit xxh3. Before anything destructive happens, every member of a
+//! candidate group is streamed side by side and the group is split wherever
+//! the contents diverge.
+//!
+//! Files are read through [`crate::io`], so the caller's cache policy and
+//! throttle apply to this pass as they do to hashing.
+
+use crate::{
+    hashing::Digest,
+    io::{self, Buffered, ReadOptions},
+    outcome::ScanError,
+    FileEntry,
+};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+const CHUNK: usize = 64 * 1024;
+
+/// Groups up to this size keep every member open while they are compared.
+/// Larger ones reopen each member for every chunk, so a group of thousands
+/// of copies does not run out of file descriptors.
+const MAX_OPEN: usize = 64;
+
+/// A file still believed identical to the others in its class.
+struct Member<'a> {
+    entry: &'a FileEntry,
+    /// Bytes compared so far.
+    offset: u64,
+    file: Option<Buffered>,
+}
+
+impl Member<'_> {
+    /// The next chunk, reopening the file at `offset` if it was not kept.
+    fn next_chunk(&mut self, keep_open: bool, opts: &ReadOptions) -> Result<Vec<u8>> {
+        let path = &self.entry.path;
+        let mut file = match self.file.take() {
+            Some(f) => f,
+            None => {
+                let source = io::open(Path::new(path), opts)
+                    .with_context(|| format!("verify: cannot open {}", path))?;
+                let mut f = Buffered::new(source, opts);
+                f.seek(SeekFrom::Start(self.offset))
+                    .with_context(|| format!("verify: cannot read {}", path))?;
+                f
+            }
+        };
+        let chunk =
+            read_chunk(&mut file).with_context(|| format!("verify: cannot read {}", path))?;
+        self.offset += chunk.len() as u64;
+        if keep_open {
+            self.file = Some(file);
+        }
+        Ok(chunk)
+    }
+}
+
+/// A digest shared by files whose contents are not actually identical.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Collision {
+    pub hash: Digest,
+    /// Paths grouped by real content; every inner list is byte-identical.
+    pub variants: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Verification<'a> {
+    /// Groups of two or more byte-identical files.
+    pub groups: Vec<Vec<&'a FileEntry>>,
+    pub collisions: Vec<Collision>,
+    /// Files that could not be read back. They are left out of `groups`.
+    pub errors: Vec<ScanError>,
+}
+
+/// Compare every group's members byte for byte.
+pub fn verify_groups<'a>(groups: &[Vec<&'a FileEntry>]) -> Result<Verification<'a>> {
+    verify_groups_with(groups, &ReadOptions::default())
+}
+
+/// [`verify_groups`], reading files with the given I/O options.
+pub fn verify_groups_with<'a>(
+    groups: &[Vec<&'a FileEntry>],
+    opts: &ReadOptions,
+) -> Result<Verification<'a>> {
+    let split: Vec<(Vec<Vec<&FileEntry>>, Vec<ScanError>)> = groups
+        .par_iter()
+        .map(|g| split_by_content(g, opts))
+        .collect();
+
+    let mut out = Verification::default();
+    for (variants, errors) in split {
+        out.errors.extend(errors);
+        if variants.len() > 1 {
+            out.collisions.push(Collision {
+                hash: variants[0][0].hash,
+                variants: variants
+                    .iter()
+                    .map(|v| v.iter().map(|e| e.path.clone()).collect())
+                    .collect(),
+            });
+        }
+        out.groups
+            .extend(variants.into_iter().filter(|v| v.len() > 1));
+    }
+    Ok(out)
+}
+
+/// Split one group into classes of byte-identical files, in input order.
+/// A member that cannot be read is dropped with an error.
+fn split_by_content<'a>(
+    group: &[&'a FileEntry],
+    opts: &ReadOptions,
+) -> (Vec<Vec<&'a FileEntry>>, Vec<ScanError>) {
+    let keep_open = group.len() <= MAX_OPEN;
+    let start: Vec<Member> = group
+        .iter()
+        .map(|&entry| Member {
+            entry,
+            offset: 0,
+            file: None,
+        })
+        .collect();
+
+    let mut pending = vec![start];
+    let mut done = Vec::new();
+    let mut errors = Vec::new();
+    while let Some(class) = pending.pop() {
+        if class.len() < 2 {
+            done.push(class.into_iter().map(|m| m.entry).collect());
+            continue;
+        }
+        let mut by_chunk: Vec<(Vec<u8>, Vec<Member>)> = Vec::new();
+        let mut index: HashMap<Vec<u8>, usize> = HashMap::new();
+        for mut m in class {
+            let chunk = match m.next_chunk(keep_open, opts) {
+                Ok(c) => c,
+                Err(e) => {
+                    errors.push(ScanError::from_anyhow(m.entry.path.clone().into(), &e));
+                    continue;
+                }
+            };
+            match index.get(&chunk) {
+                Some(&i) => by_chunk[i].1.push(m),
+                None => {
+                    index.insert(chunk.clone(), by_chunk.len());
+                    by_chunk.push((chunk, vec![m]));
+                }
+            }
+        }
+        for (chunk, members) in by_chunk {
+            if chunk.is_empty() {
+                done.push(members.into_iter().map(|m| m.entry).collect());
+            } else {
+                pending.push(members);
+            }
+        }
+    }
+
+    // Keep classes in the order their first member appeared in the group.
+    let position = |e: &FileEntry| group.iter().position(|g| std::ptr::eq(*g, e));
+    done.sort_by_key(|c: &Vec<&FileEntry>| position(c[0]));
+    (done, errors)
+}
+
+/// Fill up to `CHUNK` bytes, short only at end of file.
+fn read_chunk<R: Read>(r: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(CHUNK);
+    r.take(CHUNK as u64).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Every test entry claims the same digest, as a colliding xxh3 would.
+    const H: &str = "xxh3:00000000deadbeef";
+
+    fn entry(dir: &TempDir, name: &str, body: &[u8], hash: &str) -> FileEntry {
+        let path = dir.path().join(name);
+        fs::write(&path, body).unwrap();
+        FileEntry::test(&path.to_string_lossy(), hash)
+    }
+
+    #[test]
+    fn test_identical_files_stay_grouped() {
+        let dir = TempDir::new().unwrap();
+        let a = entry(&dir, "a", b"same bytes", H);
+        let b = entry(&dir, "b", b"same bytes", H);
+
+        let v = verify_groups(&[vec![&a, &b]]).unwrap();
+        assert_eq!(v.groups, vec![vec![&a, &b]]);
+        assert!(v.collisions.is_empty());
+    }
+
+    #[test]
+    fn test_collision_splits_group() {
+        let dir = TempDir::new().unwrap();
+        let long = vec![5u8; CHUNK + 10];
+        let mut tail = long.clone();
+        tail[CHUNK + 3] = 6;
+        let a = entry(&dir, "a", &long, H);
+        let b = entry(&dir, "b", &tail, H);
+        let c = entry(&dir, "c", &long, H);
+
+        let v = verify_groups(&[vec![&a, &b, &c]]).unwrap();
+        assert_eq!(v.groups, vec![vec![&a, &c]]);
+        assert_eq!(v.collisions.len(), 1);
+        assert_eq!(
+            v.collisions[0].variants,
+            vec![vec![a.path.clone(), c.path.clone()], vec![b.path.clone()]]
+        );
+    }
+
+    #[test]
+    fn test_length_mismatch_is_a_collision() {
+        let dir = TempDir::new().unwrap();
+        let a = entry(&dir, "a", b"prefix", H);
+        let b = entry(&dir, "b", b"prefix and more", H);
+
+        let v = verify_groups(&[vec![&a, &b]]).unwrap();
+        assert!(v.groups.is_empty());
+        assert_eq!(v.collisions.len(), 1);
+    }
+
+    #[test]
+    fn test_unreadable_member_is_dropped() {
+        let dir = TempDir::new().unwrap();
+        let a = entry(&dir, "a", b"same bytes", H);
+        let b = entry(&dir, "b", b"same bytes", H);
+        let gone = entry(&dir, "gone", b"same bytes", H);
+        fs::remove_file(&gone.path).unwrap();
+
+        let v = verify_groups(&[vec![&a, &gone, &b]]).unwrap();
+        assert_eq!(v.groups, vec![vec![&a, &b]]);
+        assert!(v.collisions.is_empty());
+        assert_eq!(v.errors.len(), 1);
+        assert_eq!(v.errors[0].path, std::path::Path::new(&gone.path));
+    }
+
+    #[test]
+    fn test_large_group_reopens_files() {
+        let dir = TempDir::new().unwrap();
+        let body = vec![9u8; CHUNK * 2 + 1];
+        let mut odd = body.clone();
+        odd[CHUNK * 2] = 8;
+        let mut files: Vec<FileEntry> = (0..MAX_OPEN + 2)
+            .map(|i| entry(&dir, &i.to_string(), &body, H))
+            .collect();
+        files.push(entry(&dir, "odd", &odd, H));
+        let group: Vec<&FileEntry> = files.iter().collect();
+
+        let v = verify_groups(&[group]).unwrap();
+        assert_eq!(v.groups.len(), 1);
+        assert_eq!(v.groups[0].len(), MAX_OPEN + 2);
+        assert_eq!(v.collisions.len(), 1);
+    }
+
+    #[test]
+    fn test_reads_through_io_options() {
+        let dir = TempDir::new().unwrap();
+        let body = vec![3u8; CHUNK + 10];
+        let mut tail = body.clone();
+        tail[CHUNK + 7] = 4;
+        let a = entry(&dir, "a", &body, H);
+        let b = entry(&dir, "b", &tail, H);
+        let c = entry(&dir, "c", &body, H);
+        let opts = ReadOptions {
+            cache: io::CachePolicy::Direct,
+            ..ReadOptions::default()
+        };
+
+        let v = verify_groups_with(&[vec![&a, &b, &c]], &opts).unwrap();
+        assert_eq!(v.groups, vec![vec![&a, &c]]);
+        assert_eq!(v.collisions.len(), 1);
+    }
+}
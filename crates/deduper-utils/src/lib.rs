@@ -12,6 +12,57 @@ pub mod file_utils {
             .map(|name| name.starts_with('.'))
             .unwrap_or(false)
     }
+
+    /// Whether `path` is hidden by any convention we know of: a leading dot
+    /// in its name, or a hidden mark on the file itself.
+    pub fn is_hidden(path: &Path) -> bool {
+        is_hidden_file(path) || has_hidden_attribute(path)
+    }
+
+    /// Whether the file at `path`, not what a link there points to, is
+    /// marked hidden: by a `user.hidden` xattr of `1` or `true` on Linux,
+    /// the `UF_HIDDEN` flag on macOS or the hidden attribute on Windows.
+    #[cfg(target_os = "linux")]
+    pub fn has_hidden_attribute(path: &Path) -> bool {
+        use std::os::unix::ffi::OsStrExt;
+
+        let Ok(c_path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+            return false;
+        };
+        let mut value = [0u8; 8];
+        // SAFETY: both names are NUL-terminated and the buffer length is
+        // passed along with it.
+        let len = unsafe {
+            libc::lgetxattr(
+                c_path.as_ptr(),
+                c"user.hidden".as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        // Missing, unsupported and oversized values all come back negative.
+        len > 0 && matches!(value[..len as usize].trim_ascii(), b"1" | b"true")
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn has_hidden_attribute(path: &Path) -> bool {
+        use std::os::macos::fs::MetadataExt;
+        const UF_HIDDEN: u32 = 0x8000;
+        std::fs::symlink_metadata(path).is_ok_and(|md| md.st_flags() & UF_HIDDEN != 0)
+    }
+
+    #[cfg(windows)]
+    pub fn has_hidden_attribute(path: &Path) -> bool {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        std::fs::symlink_metadata(path)
+            .is_ok_and(|md| md.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    pub fn has_hidden_attribute(_path: &Path) -> bool {
+        false
+    }
     
     pub fn format_file_size(size: u64) -> String {
         const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
@@ -26,3 +77,46 @@ pub mod file_utils {
         format!("{:.1} {}", size, UNITS[unit_index])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::file_utils::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_hidden_by_name() {
+        assert!(is_hidden_file(Path::new("dir/.cache")));
+        assert!(!is_hidden_file(Path::new(".config/app.toml")));
+        assert!(is_hidden(Path::new("/nonexistent/.profile")));
+        assert!(!is_hidden(Path::new("/nonexistent/profile")));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_hidden_by_xattr() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("plain.txt");
+        std::fs::write(&path, "x").unwrap();
+        assert!(!is_hidden(&path));
+
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap();
+        let value = b"1";
+        // SAFETY: NUL-terminated names and a buffer with its length.
+        let rc = unsafe {
+            libc::setxattr(
+                c_path.as_ptr(),
+                c"user.hidden".as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        if rc != 0 {
+            // tmpfs and some sandboxes refuse user xattrs.
+            return;
+        }
+        assert!(is_hidden(&path));
+    }
+}